@@ -7,6 +7,7 @@ use std::time::*;
 fn main() {
     println!("cargo:rerun-if-changed=config.json");
     println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=package.json");
     rerun_if_dir_changed("src");
     rerun_if_dir_changed("theme");
     // Load theme name from config.json and output code to load the theme via include_dir!
@@ -16,6 +17,7 @@ fn main() {
     generate_build_timestamp();
     generate_theme_loader(&config);
     generate_hljs_loader(&config);
+    generate_katex_loader(&config);
 }
 
 fn rerun_if_dir_changed(dir: &str) {
@@ -80,3 +82,40 @@ fn generate_hljs_loader(config: &serde_json::Value) {
     out_file.write_all(rs_code.as_bytes()).unwrap();
     out_file.sync_data().unwrap();
 }
+
+fn generate_katex_loader(config: &serde_json::Value) {
+    // Tell rustc that `cfg(katex)` is expected either way
+    println!("cargo:rustc-check-cfg=cfg(katex)");
+    let enabled = match config.get("katex") {
+        Some(val) => val.as_bool().expect("`katex` is not a boolean"),
+        None => false
+    };
+
+    if !enabled {
+        // Nothing requires KaTeX then, so webpack won't bundle it
+        return;
+    }
+
+    println!("cargo:rustc-cfg=katex");
+    // The stylesheet is loaded separately, so the version has to be exact
+    // for it to match what's bundled
+    let package: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string("./package.json").unwrap()).unwrap();
+    let version = match package["devDependencies"]["katex"].as_str() {
+        Some(version) => version,
+        None => panic!("Please add `katex` to `devDependencies` in `package.json`")
+    };
+    if !version.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        panic!("Please pin `katex` to an exact version in `package.json`");
+    }
+
+    let js_code = "module.exports = require(\\\"katex\\\");";
+    let rs_code = fs::read_to_string("./src/katex_tpl.rs").unwrap();
+    let rs_code = format!(
+        "#[wasm_bindgen(inline_js = \"{}\")]\n{}\n\npub const KATEX_VERSION: &'static str = \"{}\";\n",
+        js_code, rs_code, version);
+    let out_path = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let mut out_file = fs::File::create(out_path.join("load_katex.rs")).unwrap();
+    out_file.write_all(rs_code.as_bytes()).unwrap();
+    out_file.sync_data().unwrap();
+}
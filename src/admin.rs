@@ -0,0 +1,51 @@
+// One-shot maintenance endpoints
+use crate::blog;
+use crate::router::Router;
+use crate::utils::*;
+use web_sys::*;
+
+pub fn build_routes(router: &mut Router) {
+    router.add_route("/resort", &resort_posts);
+    router.add_route("/relink", &relink_posts);
+}
+
+// Sort the home page by post timestamps (newest first)
+// Mainly useful when turning on `sort_by_timestamp` in config for an
+// existing blog, but works regardless of it
+async fn resort_posts(req: Request, url: Url) -> MyResult<Response> {
+    verify_secret!(url, params);
+    if req.method() != "POST" {
+        return Err(Error::BadRequest("Unsupported method".into()));
+    }
+
+    let posts = blog::PostsList::sort_by_timestamp().await?;
+    Response::new_with_opt_str_and_init(
+        Some(&serde_json::json!({ "posts": posts }).to_string()),
+        ResponseInit::new()
+            .status(200)
+            .headers(headers!{
+                "Content-Type" => "application/json",
+                "Cache-Control" => "no-cache"
+            }.as_ref())
+    ).internal_err()
+}
+
+// Rewrite the previous / next post pointers of all published posts
+// Needed after editing `posts_list` manually
+async fn relink_posts(req: Request, url: Url) -> MyResult<Response> {
+    verify_secret!(url, params);
+    if req.method() != "POST" {
+        return Err(Error::BadRequest("Unsupported method".into()));
+    }
+
+    let posts = blog::PostNeighbours::rebuild().await?;
+    Response::new_with_opt_str_and_init(
+        Some(&serde_json::json!({ "posts": posts }).to_string()),
+        ResponseInit::new()
+            .status(200)
+            .headers(headers!{
+                "Content-Type" => "application/json",
+                "Cache-Control" => "no-cache"
+            }.as_ref())
+    ).internal_err()
+}
@@ -0,0 +1,372 @@
+// Backup of the entire blog
+// Everything that cannot be regenerated from posts (i.e. everything
+// except for the content caches) is dumped into one JSON archive
+use crate::{blog, store};
+use crate::router::Router;
+use crate::utils::*;
+use js_sys::{Array, Date, Function, Object, Promise, Reflect, Uint8Array};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+use std::vec::Vec;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+use web_sys::*;
+
+// Bump this whenever the archive format changes incompatibly
+const ARCHIVE_VERSION: u32 = 1;
+
+pub fn build_routes(router: &mut Router) {
+    router.add_route("/export", &export_archive);
+    router.add_route("/import", &import_archive);
+}
+
+// A reason why an archive cannot be imported as-is
+#[derive(Serialize)]
+struct ImportConflict {
+    // One of
+    //   "duplicate_uuid": the UUID appears more than once in the archive
+    //   "duplicate_url": the URL belongs to more than one post in the archive
+    //   "unknown_post": `posts_list` or `pages_list` refers to a UUID not in the archive
+    //   "uuid_exists": a post with the UUID already exists on this blog
+    //   "url_taken": the URL already belongs to another post on this blog
+    kind: &'static str,
+    uuid: String,
+    url: Option<String>
+}
+
+#[derive(Serialize)]
+struct ImportReport {
+    dry_run: bool,
+    // Whether anything was actually written
+    imported: bool,
+    posts: usize,
+    conflicts: Vec<ImportConflict>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BlogArchive {
+    // Format version of the archive (ARCHIVE_VERSION)
+    pub version: u32,
+    // The UNIX timestamp (in seconds) when the archive was created
+    pub created_at: u64,
+    // Every post, including unlisted ones
+    pub posts: Vec<blog::Post>,
+    // UUIDs of published posts, in the order they appear on the home page
+    pub posts_list: Vec<String>,
+    // UUIDs of published pages, in navigation order
+    // Absent in archives created before pages were introduced
+    #[serde(default)]
+    pub pages_list: Vec<String>,
+    // UUIDs of posts waiting for their `publish_at`
+    // Absent in archives created before this was exported, in which case
+    // nothing is scheduled after import
+    #[serde(default)]
+    pub pending_posts: Vec<String>,
+    // URL -> UUID, including old URLs of posts that have been changed
+    pub url_mappings: BTreeMap<String, String>,
+    // Remote URLs allowed through the image proxy
+    pub cache_whitelist: Vec<String>
+}
+
+// Pieces of the archive as exported, in the format of `BlogArchive`
+// Posts and URL mappings are only loaded when their turn comes, so that
+// the export never holds more than one post in memory
+enum ArchiveChunk {
+    // JSON written as-is
+    Raw(String),
+    // A post, by UUID
+    Post(String),
+    // An entry of `url_mappings`, by URL
+    UrlMapping(String)
+}
+
+impl ArchiveChunk {
+    async fn to_json(&self) -> MyResult<String> {
+        match self {
+            ArchiveChunk::Raw(json) => Ok(json.clone()),
+            ArchiveChunk::Post(uuid) =>
+                serde_json::to_string(&blog::Post::find_by_uuid(uuid).await?).internal_err(),
+            ArchiveChunk::UrlMapping(url) => {
+                let uuid = store::get_str(&format!("{}{}", blog::URL_MAPPING_KEY_PREFIX, url)).await?;
+                Ok(format!("{}:{}",
+                    serde_json::to_string(url).internal_err()?,
+                    serde_json::to_string(&uuid).internal_err()?))
+            }
+        }
+    }
+}
+
+impl BlogArchive {
+    // Everything to be exported, as chunks to be written one after another
+    // Only keys and the lists are loaded here
+    async fn chunks(created_at: u64) -> MyResult<VecDeque<ArchiveChunk>> {
+        let mut chunks = VecDeque::new();
+        chunks.push_back(ArchiveChunk::Raw(format!(
+            "{{\"version\":{},\"created_at\":{},\"posts\":[", ARCHIVE_VERSION, created_at)));
+        for (i, uuid) in store::list_all_stripped(blog::POST_KEY_PREFIX).await?.into_iter().enumerate() {
+            if i > 0 {
+                chunks.push_back(ArchiveChunk::Raw(",".into()));
+            }
+            chunks.push_back(ArchiveChunk::Post(uuid));
+        }
+
+        let pages_list: Vec<String> = blog::PagesList::load().await?.pages.into_iter().map(|p| p.uuid).collect();
+        chunks.push_back(ArchiveChunk::Raw(format!(
+            "],\"posts_list\":{},\"pages_list\":{},\"pending_posts\":{},\"url_mappings\":{{",
+            serde_json::to_string(&blog::PostsList::load().await?.posts).internal_err()?,
+            serde_json::to_string(&pages_list).internal_err()?,
+            serde_json::to_string(&blog::PostsList::load_pending().await?.posts).internal_err()?)));
+        for (i, url) in store::list_all_stripped(blog::URL_MAPPING_KEY_PREFIX).await?.into_iter().enumerate() {
+            if i > 0 {
+                chunks.push_back(ArchiveChunk::Raw(",".into()));
+            }
+            chunks.push_back(ArchiveChunk::UrlMapping(url));
+        }
+
+        chunks.push_back(ArchiveChunk::Raw(format!(
+            "}},\"cache_whitelist\":{}}}",
+            serde_json::to_string(
+                &store::list_all_stripped(blog::CACHE_WHITELIST_KEY_PREFIX).await?
+            ).internal_err()?)));
+        Ok(chunks)
+    }
+
+    // A stream writing out one chunk each time it is read from
+    // The response has already started by the time a chunk fails to load,
+    // so the stream is errored then, and the client sees a broken download
+    fn stream(chunks: VecDeque<ArchiveChunk>) -> MyResult<ReadableStream> {
+        let chunks = Rc::new(RefCell::new(chunks));
+        let pull = Closure::wrap(Box::new(move |controller: JsValue| {
+            let chunks = chunks.clone();
+            future_to_promise(async move {
+                let next = chunks.borrow_mut().pop_front();
+                let (method, arg) = match next {
+                    Some(chunk) => match chunk.to_json().await {
+                        Ok(json) => ("enqueue", Uint8Array::from(json.as_bytes()).into()),
+                        Err(err) => ("error", JsValue::from_str(&Into::<String>::into(err)))
+                    },
+                    None => ("close", JsValue::UNDEFINED)
+                };
+                Reflect::get(&controller, &method.into())?
+                    .unchecked_into::<Function>()
+                    .call1(&controller, &arg)?;
+                Ok(JsValue::UNDEFINED)
+            })
+        }) as Box<dyn FnMut(JsValue) -> Promise>);
+
+        // web_sys has no way to construct a ReadableStream from a source
+        let source = Object::new();
+        Reflect::set(&source, &"pull".into(), &pull.into_js_value()).internal_err()?;
+        let constructor = Reflect::get(&js_sys::global(), &"ReadableStream".into()).internal_err()?;
+        Ok(Reflect::construct(constructor.unchecked_ref::<Function>(), &Array::of1(&source))
+            .internal_err()?.unchecked_into())
+    }
+
+    // Check the archive against itself and against what's already on the blog
+    // Nothing is written here
+    async fn find_conflicts(&self) -> MyResult<Vec<ImportConflict>> {
+        let mut conflicts = vec![];
+
+        let mut uuids = HashSet::new();
+        for post in self.posts.iter() {
+            if !uuids.insert(post.uuid.as_str()) {
+                conflicts.push(ImportConflict {
+                    kind: "duplicate_uuid", uuid: post.uuid.clone(), url: None
+                });
+            } else if blog::Post::find_by_uuid(&post.uuid).await.is_ok() {
+                conflicts.push(ImportConflict {
+                    kind: "uuid_exists", uuid: post.uuid.clone(), url: None
+                });
+            }
+        }
+
+        for uuid in self.posts_list.iter().chain(self.pages_list.iter()).chain(self.pending_posts.iter()) {
+            if !uuids.contains(uuid.as_str()) {
+                conflicts.push(ImportConflict {
+                    kind: "unknown_post", uuid: uuid.clone(), url: None
+                });
+            }
+        }
+
+        for (url, uuid) in self.url_owners().iter() {
+            if let Some(existing) = store::try_get_str(
+                    &format!("{}{}", blog::URL_MAPPING_KEY_PREFIX, url)).await? {
+                if existing != *uuid {
+                    conflicts.push(ImportConflict {
+                        kind: "url_taken", uuid: uuid.to_string(), url: Some(url.to_string())
+                    });
+                }
+            }
+        }
+
+        // Current URLs of posts must not be claimed by another post,
+        // either as its current URL or as an old one
+        let mut owners: HashMap<&str, &str> = HashMap::new();
+        for post in self.posts.iter() {
+            owners.insert(&post.url, &post.uuid);
+        }
+        for (url, uuid) in self.url_mappings.iter() {
+            if let Some(owner) = owners.get(url.as_str()) {
+                if owner != uuid && uuids.contains(uuid.as_str()) {
+                    conflicts.push(ImportConflict {
+                        kind: "duplicate_url", uuid: uuid.clone(), url: Some(url.clone())
+                    });
+                }
+            }
+        }
+        let mut seen_urls = HashSet::new();
+        for post in self.posts.iter() {
+            if !seen_urls.insert(post.url.as_str()) {
+                conflicts.push(ImportConflict {
+                    kind: "duplicate_url", uuid: post.uuid.clone(), url: Some(post.url.clone())
+                });
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    // Every URL that will be mapped after import, and the post it maps to
+    // Mappings to posts not in the archive (left behind by deleted posts) are dropped
+    fn url_owners(&self) -> BTreeMap<&str, &str> {
+        let uuids: HashSet<&str> = self.posts.iter().map(|p| p.uuid.as_str()).collect();
+        let mut ret = BTreeMap::new();
+        for (url, uuid) in self.url_mappings.iter() {
+            if uuids.contains(uuid.as_str()) {
+                ret.insert(url.as_str(), uuid.as_str());
+            }
+        }
+        // Current URLs always win over old ones
+        for post in self.posts.iter() {
+            ret.insert(post.url.as_str(), post.uuid.as_str());
+        }
+        ret
+    }
+
+    // Write everything in the archive to storage
+    // Posts already on the blog but not in the archive are kept,
+    // and listed after the imported ones
+    async fn write(self) -> MyResult<()> {
+        for (url, uuid) in self.url_owners() {
+            blog::Post::create_url_mapping(url, uuid).await?;
+        }
+
+        for url in self.cache_whitelist.iter() {
+            blog::PostContentCache::whitelist_external_url_for_cache(url).await?;
+        }
+
+        // Tag and author lists, the pinned list and series indices are
+        // not in the archive, since they can be rebuilt from posts
+        let mut posts_by_uuid = HashMap::new();
+        for post in self.posts.iter() {
+            if self.posts_list.contains(&post.uuid) {
+                blog::PostsList::update_tags(&post.uuid, &[], &post.tags).await?;
+                blog::PostsList::set_pinned(&post.uuid, post.pinned).await?;
+                blog::PostsList::set_author(&post.uuid, None, post.author.as_deref()).await?;
+                blog::Series::sync_post(&post.uuid, None, Some(post)).await?;
+            }
+        }
+        // Not every post with `publish_at` is waiting: unlisted posts never are
+        for uuid in self.pending_posts.iter() {
+            blog::PostsList::add_pending(uuid).await?;
+        }
+
+        for mut post in self.posts {
+            // These are new posts on this blog
+            post.version = 0;
+            blog::PostContentCache::find_or_render(&post).await;
+            post.write_to_kv().await?;
+            // Pointers of listed posts are written along with the list below
+            if !self.posts_list.contains(&post.uuid) {
+                blog::PostNeighbours::clear(&post.uuid).await?;
+            }
+            posts_by_uuid.insert(post.uuid.clone(), post);
+        }
+
+        // Imported pages go after those already on the blog
+        for uuid in self.pages_list.iter() {
+            blog::PagesList::sync_page(uuid, posts_by_uuid.get(uuid)).await?;
+        }
+
+        let imported = self.posts_list;
+        blog::PostsList::update(|posts| {
+            let mut list = imported.clone();
+            for uuid in posts.iter() {
+                if !list.contains(uuid) {
+                    list.push(uuid.clone());
+                }
+            }
+            *posts = list;
+        }).await?;
+        if crate::CONFIG.sort_by_timestamp {
+            blog::PostsList::sort_by_timestamp().await?;
+        }
+        Ok(())
+    }
+}
+
+async fn export_archive(req: Request, url: Url) -> MyResult<Response> {
+    verify_secret!(url, params);
+    if req.method() != "GET" {
+        return Err(Error::BadRequest("Unsupported method".into()));
+    }
+
+    let created_at = Date::now() as u64 / 1000;
+    let stream = BlogArchive::stream(BlogArchive::chunks(created_at).await?)?;
+    let file_name = format!("paprika-backup-{}.json", created_at);
+    Response::new_with_opt_readable_stream_and_init(
+        Some(&stream),
+        ResponseInit::new()
+            .status(200)
+            .headers(headers!{
+                "Content-Type" => "application/json",
+                "Content-Disposition" => &format!("attachment; filename=\"{}\"", file_name),
+                "Cache-Control" => "no-cache"
+            }.as_ref())
+    ).internal_err()
+}
+
+// Import an archive created by `/export`
+// With `dry_run=true` in the query string, only report conflicts
+// Without it, the archive is imported only when there is no conflict
+async fn import_archive(req: Request, url: Url) -> MyResult<Response> {
+    verify_secret!(url, params);
+    if req.method() != "POST" {
+        return Err(Error::BadRequest("Unsupported method".into()));
+    }
+
+    let dry_run = params.get("dry_run").map(|v| v == "true" || v == "1").unwrap_or(false);
+    let archive: BlogArchive = serde_json::from_str(
+        &JsFuture::from(req.text().internal_err()?)
+            .await.internal_err()?
+            .as_string().ok_or(Error::BadRequest("Unable to parse POST body".into()))?
+    ).map_err(|e| Error::BadRequest(format!("Malformed archive: {}", e)))?;
+    if archive.version != ARCHIVE_VERSION {
+        return Err(Error::BadRequest(format!("Unsupported archive version {}", archive.version)));
+    }
+
+    let conflicts = archive.find_conflicts().await?;
+    let mut report = ImportReport {
+        dry_run,
+        imported: false,
+        posts: archive.posts.len(),
+        conflicts
+    };
+    if !dry_run && report.conflicts.is_empty() {
+        archive.write().await?;
+        report.imported = true;
+    }
+
+    Response::new_with_opt_str_and_init(
+        Some(&serde_json::to_string(&report).internal_err()?),
+        ResponseInit::new()
+            .status(if report.conflicts.is_empty() { 200 } else { 409 })
+            .headers(headers!{
+                "Content-Type" => "application/json",
+                "Cache-Control" => "no-cache"
+            }.as_ref())
+    ).internal_err()
+}
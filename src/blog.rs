@@ -4,60 +4,464 @@
 // parts are stroed in different records. This also increases
 // efficiency, since the program won't need to load anything
 // unnecessary from KV.
-use crate::store;
+use crate::store::{self, Versioned};
 use crate::utils::*;
-use js_sys::{JsString, RegExp};
+use js_sys::{Date, JsString, RegExp};
 use pulldown_cmark::*;
 use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
 use std::vec::Vec;
-use wasm_bindgen::{JsCast, JsValue};
-use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::future_to_promise;
 
 // A list of the UUIDs of all published blog posts
 // This should be SORTED with the newest posts at lower indices (closer to 0)
 // The user may edit this via KV UI to change ordering and such
+// (edit the `posts` array; leave `version` alone)
 // by default new posts are always added to the top
-#[derive(Serialize, Deserialize)]
-pub struct PostsList(pub Vec<String>);
+#[derive(Serialize)]
+pub struct PostsList {
+    // Incremented on every write; see store::compare_and_put
+    pub version: u64,
+    pub posts: Vec<String>
+}
+
+// Older versions stored the list as a bare array
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PostsListRecord {
+    Versioned { version: u64, posts: Vec<String> },
+    Legacy(Vec<String>)
+}
+
+impl Versioned for PostsList {
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+
+const POSTS_LIST_KEY: &'static str = "posts_list";
+// When `posts_list` cannot be decoded, its raw value is copied to
+// `posts_list_recovery_<sha1 of value>` before anything else happens
+const POSTS_LIST_RECOVERY_KEY_PREFIX: &'static str = "posts_list_recovery_";
+// Published posts with a tag are listed in `tag_posts_<tag>`, in the
+// same format as `posts_list`. These lists only tell which posts have
+// the tag; tag pages always show posts in the order of `posts_list`.
+pub const TAG_POSTS_KEY_PREFIX: &'static str = "tag_posts_";
+// Published posts by an author, stored as `author_posts_<id>`,
+// in the same format as `posts_list`. Like tag lists, these only
+// tell which posts are by the author.
+pub const AUTHOR_POSTS_KEY_PREFIX: &'static str = "author_posts_";
+// Posts waiting to be published by the scheduler (see `crate::schedule`),
+// in the same format as `posts_list`
+const PENDING_POSTS_KEY: &'static str = "pending_posts";
+// Published posts with `pinned` set, in the same format as `posts_list`
+const PINNED_POSTS_KEY: &'static str = "pinned_posts";
 
 impl PostsList {
-    pub async fn load() -> PostsList {
-        match store::get_obj("posts_list").await {
-            Ok(v) => PostsList(v),
-            // Don't panic on empty
-            // TODO: What if the user messed up when editing?
-            //       That would cause a decode failure and all data will be gone
-            //       if a new post is added or updated (overwriting the KV value) 
-            //       under this logic
-            //       (if no new post is added then nothing bad would happen;
-            //        the user would probably notice when trying to visit the blog home page)
-            Err(_) => PostsList(vec![])
+    fn tag_to_key(tag: &str) -> String {
+        format!("{}{}", TAG_POSTS_KEY_PREFIX, tag)
+    }
+
+    fn author_to_key(id: &str) -> String {
+        format!("{}{}", AUTHOR_POSTS_KEY_PREFIX, id)
+    }
+
+    // An absent list is fine (nothing has been published yet),
+    // but a list that exists and cannot be decoded (e.g. the user
+    // messed up when editing it manually) is an error. Since every
+    // write to the list starts by loading it, this also means that
+    // a corrupted list can never be overwritten by add_post / remove_post,
+    // which would otherwise silently wipe out the whole index.
+    pub async fn load() -> MyResult<PostsList> {
+        Self::load_key(POSTS_LIST_KEY).await
+    }
+
+    // Published posts with the tag `tag`, in no particular order
+    pub async fn load_tag(tag: &str) -> MyResult<PostsList> {
+        Self::load_key(&Self::tag_to_key(tag)).await
+    }
+
+    // Published posts by the author `id`, in no particular order
+    pub async fn load_author(id: &str) -> MyResult<PostsList> {
+        Self::load_key(&Self::author_to_key(id)).await
+    }
+
+    // Posts with `publish_at` set, in no particular order
+    pub async fn load_pending() -> MyResult<PostsList> {
+        Self::load_key(PENDING_POSTS_KEY).await
+    }
+
+    // Published posts with `pinned` set, in no particular order
+    pub async fn load_pinned() -> MyResult<PostsList> {
+        Self::load_key(PINNED_POSTS_KEY).await
+    }
+
+    async fn load_key(key: &str) -> MyResult<PostsList> {
+        let raw = match store::try_get_str(key).await? {
+            Some(raw) => raw,
+            None => return Ok(PostsList { version: 0, posts: vec![] })
+        };
+
+        match serde_json::from_str(&raw) {
+            Ok(PostsListRecord::Versioned { version, posts }) => Ok(PostsList { version, posts }),
+            Ok(PostsListRecord::Legacy(posts)) => Ok(PostsList { version: 0, posts }),
+            Err(e) => {
+                // Keep a copy of the broken value so that it can be recovered
+                // even if the user decides to reset the list
+                // The key depends only on the value, so this is only written once
+                let backup_key = format!("{}{}", POSTS_LIST_RECOVERY_KEY_PREFIX, sha1(&raw).await);
+                if store::try_get_str(&backup_key).await?.is_none() {
+                    store::put_str(&backup_key, &raw).await?;
+                }
+                Err(Error::CorruptedData(format!(
+                    "`{}` cannot be decoded ({}). Its value has been backed up to `{}`. \
+                    Please fix `{}` manually; it will not be updated until then.",
+                    key, e, backup_key, key)))
+            }
         }
     }
 
-    pub fn has_post(&self, uuid: &str) -> bool {
-        self.0.contains(&uuid.into())
+    // Load the latest list, apply `f` to it and write it back
+    // If the list was changed by someone else in the meantime
+    // (e.g. publishing from two devices at once), start over
+    // with the new list, so that their change is not lost
+    // Neighbour pointers of the posts whose position changed are updated afterwards
+    pub async fn update<F: FnMut(&mut Vec<String>)>(f: F) -> MyResult<()> {
+        let (old, new) = Self::update_key_diff(POSTS_LIST_KEY, f).await?;
+        PostNeighbours::sync(&old, &new).await
     }
 
-    // Add a post to the list and then update the record in KV
-    // Also consumes self, as this should normally be the last action
-    // in an API call
-    pub async fn add_post(mut self, uuid: &str) -> MyResult<()> {
-        if self.has_post(uuid) {
-            return Ok(());
+    async fn update_key<F: FnMut(&mut Vec<String>)>(key: &str, f: F) -> MyResult<()> {
+        Self::update_key_diff(key, f).await.map(|_| ())
+    }
+
+    // Returns the list as it was before and after the successful write
+    // Nothing is written if `f` leaves the list as it was
+    async fn update_key_diff<F: FnMut(&mut Vec<String>)>(
+        key: &str, mut f: F
+    ) -> MyResult<(Vec<String>, Vec<String>)> {
+        for _ in 0..store::MAX_CAS_ATTEMPTS {
+            let mut list = Self::load_key(key).await?;
+            let old = list.posts.clone();
+            f(&mut list.posts);
+            if list.posts == old {
+                return Ok((old, list.posts));
+            }
+            if store::compare_and_put(key, &mut list, true).await? {
+                return Ok((old, list.posts));
+            }
         }
 
-        self.0.insert(0, uuid.into());
-        store::put_obj_pretty("posts_list", self.0).await
+        Err(Error::Conflict(format!("`{}` is being updated concurrently, please try again", key)))
+    }
+
+    // Add a post to the list, or move it if needed
+    // By default, new posts are added to the top and posts already
+    // present stay where they are. With `sort_by_timestamp` in config,
+    // the list is kept sorted by `timestamp` (newest first), with posts
+    // of the same timestamp in the order they were added (newest first),
+    // and a post is moved when its timestamp no longer fits its position.
+    pub async fn add_post(uuid: &str, timestamp: u64) -> MyResult<()> {
+        if !crate::CONFIG.sort_by_timestamp {
+            return Self::update(|posts| Self::insert_uuid(posts, uuid)).await;
+        }
+
+        let mut posts = Self::load().await?.posts;
+        if let Some(pos) = posts.iter().position(|p| p == uuid) {
+            let newer = match pos {
+                0 => std::u64::MAX,
+                _ => Self::post_timestamp(&posts[pos - 1]).await
+            };
+            let older = match posts.get(pos + 1) {
+                Some(next) => Self::post_timestamp(next).await,
+                None => 0
+            };
+            if newer >= timestamp && timestamp >= older {
+                return Ok(());
+            }
+            posts.remove(pos);
+        }
+
+        // The list is sorted, so a binary search only needs to load a few posts
+        // Find the first post not newer than this one
+        let (mut lo, mut hi) = (0, posts.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if Self::post_timestamp(&posts[mid]).await <= timestamp {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        // Insert relative to that post, in case the list has changed in the meantime
+        let anchor = posts.get(lo).cloned();
+        Self::update(|posts| {
+            posts.retain(|p| p != uuid);
+            match &anchor {
+                Some(anchor) => {
+                    let pos = posts.iter().position(|p| p == anchor).unwrap_or(0);
+                    posts.insert(pos, uuid.into());
+                },
+                None => posts.push(uuid.into())
+            }
+        }).await
+    }
+
+    // Missing posts count as the oldest
+    async fn post_timestamp(uuid: &str) -> u64 {
+        match Post::find_by_uuid(uuid).await {
+            Ok(post) => post.timestamp,
+            Err(_) => 0
+        }
+    }
+
+    // Sort the whole list by timestamp (newest first)
+    // Posts with the same timestamp keep their relative order
+    // Returns the number of posts in the list
+    pub async fn sort_by_timestamp() -> MyResult<usize> {
+        let mut timestamps = std::collections::HashMap::new();
+        for uuid in Self::load().await?.posts {
+            let timestamp = Self::post_timestamp(&uuid).await;
+            timestamps.insert(uuid, timestamp);
+        }
+
+        let mut len = 0;
+        Self::update(|posts| {
+            // Posts added in the meantime stay on top
+            posts.sort_by_key(|p| std::cmp::Reverse(
+                timestamps.get(p).cloned().unwrap_or(std::u64::MAX)));
+            len = posts.len();
+        }).await?;
+        Ok(len)
     }
 
     // Remove a post from published list
     // may be used when deleting / unpublishing a post
     // Does nothing if uuid not found in list
-    pub async fn remove_post(mut self, uuid: &str) -> MyResult<()> {
-        self.0.remove_item(&uuid);
-        store::put_obj_pretty("posts_list", self.0).await
+    pub async fn remove_post(uuid: &str) -> MyResult<()> {
+        Self::update(|posts| {
+            posts.remove_item(&uuid);
+        }).await
+    }
+
+    pub async fn add_pending(uuid: &str) -> MyResult<()> {
+        Self::update_key(PENDING_POSTS_KEY, |posts| Self::insert_uuid(posts, uuid)).await
+    }
+
+    pub async fn remove_pending(uuid: &str) -> MyResult<()> {
+        Self::update_key(PENDING_POSTS_KEY, |posts| {
+            posts.remove_item(&uuid);
+        }).await
+    }
+
+    fn insert_uuid(posts: &mut Vec<String>, uuid: &str) {
+        if !posts.iter().any(|p| p == uuid) {
+            posts.insert(0, uuid.into());
+        }
+    }
+
+    // Move a post from the lists of `old_tags` to those of `new_tags`
+    // Lists of tags in both are left alone, except for adding the post
+    // if it's missing from them
+    pub async fn update_tags(uuid: &str, old_tags: &[String], new_tags: &[String]) -> MyResult<()> {
+        for tag in old_tags.iter().filter(|t| !new_tags.contains(t)) {
+            Self::update_key(&Self::tag_to_key(tag), |posts| {
+                posts.remove_item(&uuid);
+            }).await?;
+        }
+        for tag in new_tags.iter() {
+            Self::update_key(&Self::tag_to_key(tag), |posts| Self::insert_uuid(posts, uuid)).await?;
+        }
+        Ok(())
+    }
+
+    // Move a post from the list of `old_author` to that of `new_author`
+    // Like `update_tags`, the post is added to the new list if it's missing
+    pub async fn set_author(uuid: &str, old_author: Option<&str>, new_author: Option<&str>) -> MyResult<()> {
+        if let Some(old_author) = old_author.filter(|a| Some(*a) != new_author) {
+            Self::update_key(&Self::author_to_key(old_author), |posts| {
+                posts.remove_item(&uuid);
+            }).await?;
+        }
+        if let Some(new_author) = new_author {
+            Self::update_key(&Self::author_to_key(new_author), |posts| Self::insert_uuid(posts, uuid)).await?;
+        }
+        Ok(())
+    }
+
+    // Most posts are never pinned, so don't write anything if nothing changes
+    pub async fn set_pinned(uuid: &str, pinned: bool) -> MyResult<()> {
+        if Self::load_pinned().await?.posts.iter().any(|p| p == uuid) == pinned {
+            return Ok(());
+        }
+
+        Self::update_key(PINNED_POSTS_KEY, |posts| {
+            if pinned {
+                Self::insert_uuid(posts, uuid);
+            } else {
+                posts.remove_item(&uuid);
+            }
+        }).await
+    }
+}
+
+// The posts next to a published post in `posts_list`, stored as
+// `post_neighbours_<uuid>`, so that the post page doesn't have to
+// load the whole list, nor the neighbours themselves. `prev` is the
+// older post (further down the list) and `next` the newer one.
+// Unlisted posts and pages have both as None.
+// Since titles and URLs are copied here, the records of the neighbours
+// of a post have to be refreshed whenever it is saved (see `relink`).
+#[derive(Serialize, Deserialize, PartialEq, Default)]
+pub struct PostNeighbours {
+    pub prev: Option<NeighbourLink>,
+    pub next: Option<NeighbourLink>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct NeighbourLink {
+    pub uuid: String,
+    pub title: String,
+    pub url: String
+}
+
+impl NeighbourLink {
+    fn to_post(post: &Post) -> NeighbourLink {
+        NeighbourLink {
+            uuid: post.uuid.clone(),
+            title: post.title.clone(),
+            url: post.url.clone()
+        }
+    }
+
+    // None if the post doesn't exist (anymore, or not yet while it's
+    // being published, in which case `relink` fills it in afterwards)
+    async fn load(uuid: Option<&String>) -> Option<NeighbourLink> {
+        match Post::find_by_uuid(uuid?).await {
+            Ok(post) => Some(Self::to_post(&post)),
+            Err(_) => None
+        }
+    }
+}
+
+pub const NEIGHBOURS_KEY_PREFIX: &'static str = "post_neighbours_";
+
+impl PostNeighbours {
+    fn uuid_to_key(uuid: &str) -> String {
+        format!("{}{}", NEIGHBOURS_KEY_PREFIX, uuid)
+    }
+
+    async fn compute(posts: &[String], uuid: &str) -> PostNeighbours {
+        Self::at(posts, posts.iter().position(|p| p == uuid)).await
+    }
+
+    async fn at(posts: &[String], pos: Option<usize>) -> PostNeighbours {
+        let (prev, next) = Self::uuids_at(posts, pos);
+        PostNeighbours {
+            prev: NeighbourLink::load(prev).await,
+            next: NeighbourLink::load(next).await
+        }
+    }
+
+    fn uuids_at(posts: &[String], pos: Option<usize>) -> (Option<&String>, Option<&String>) {
+        match pos {
+            Some(pos) => (posts.get(pos + 1), match pos {
+                0 => None,
+                _ => posts.get(pos - 1)
+            }),
+            None => (None, None)
+        }
+    }
+
+    // Posts that haven't been touched since pointers were introduced
+    // have none yet (or only UUIDs, which don't parse anymore);
+    // compute them from the list in that case
+    // They are not written here: this is called when serving posts,
+    // where a write could race with publishing and leave stale pointers.
+    // `sync` or `rebuild` (`/relink`) will fill them in.
+    pub async fn load(uuid: &str) -> MyResult<PostNeighbours> {
+        if let Some(neighbours) = store::try_get_str(&Self::uuid_to_key(uuid)).await? {
+            if let Ok(neighbours) = serde_json::from_str(&neighbours) {
+                return Ok(neighbours);
+            }
+        }
+        Ok(Self::compute(&PostsList::load().await?.posts, uuid).await)
+    }
+
+    // Write the pointers of every post whose neighbours differ between the
+    // two versions of the list, including posts that left the list
+    async fn sync(old: &[String], new: &[String]) -> MyResult<()> {
+        let old_pos: HashMap<&str, usize> = old.iter().enumerate().map(|(i, p)| (p.as_str(), i)).collect();
+        let new_pos: HashMap<&str, usize> = new.iter().enumerate().map(|(i, p)| (p.as_str(), i)).collect();
+        let removed = old.iter().filter(|p| !new_pos.contains_key(p.as_str()));
+        for uuid in new.iter().chain(removed) {
+            let new_at = new_pos.get(uuid.as_str()).cloned();
+            if Self::uuids_at(new, new_at) != Self::uuids_at(old, old_pos.get(uuid.as_str()).cloned()) {
+                store::put_obj(&Self::uuid_to_key(uuid), &Self::at(new, new_at).await).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Point the neighbours of a listed post to it as it was just saved,
+    // since its title or URL may have changed, or it may not have existed
+    // yet when the list was updated
+    pub async fn relink(post: &Post) -> MyResult<()> {
+        let link = NeighbourLink::to_post(post);
+        let neighbours = Self::load(&post.uuid).await?;
+        if let Some(prev) = neighbours.prev {
+            let mut theirs = Self::load(&prev.uuid).await?;
+            if theirs.next.as_ref() != Some(&link) {
+                theirs.next = Some(link.clone());
+                store::put_obj(&Self::uuid_to_key(&prev.uuid), &theirs).await?;
+            }
+        }
+        if let Some(next) = neighbours.next {
+            let mut theirs = Self::load(&next.uuid).await?;
+            if theirs.prev.as_ref() != Some(&link) {
+                theirs.prev = Some(link);
+                store::put_obj(&Self::uuid_to_key(&next.uuid), &theirs).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Give a post that isn't listed (unlisted, scheduled or a page) an
+    // empty record, so that serving it doesn't fall back to loading the list
+    pub async fn clear(uuid: &str) -> MyResult<()> {
+        let key = Self::uuid_to_key(uuid);
+        let empty = PostNeighbours::default();
+        let current = store::try_get_str(&key).await?
+            .and_then(|raw| serde_json::from_str::<PostNeighbours>(&raw).ok());
+        if current.as_ref() != Some(&empty) {
+            store::put_obj(&key, &empty).await?;
+        }
+        Ok(())
+    }
+
+    // Rewrite the pointers of all posts from `posts_list`,
+    // e.g. after editing the list manually
+    // Returns the number of posts in the list
+    pub async fn rebuild() -> MyResult<usize> {
+        let posts = PostsList::load().await?.posts;
+        for uuid in posts.iter() {
+            store::put_obj(&Self::uuid_to_key(uuid), &Self::compute(&posts, uuid).await).await?;
+        }
+        for uuid in store::list_all_stripped(POST_KEY_PREFIX).await? {
+            if !posts.contains(&uuid) {
+                Self::clear(&uuid).await?;
+            }
+        }
+        Ok(posts.len())
+    }
+
+    async fn delete_by_uuid(uuid: &str) -> MyResult<()> {
+        store::delete(&Self::uuid_to_key(uuid)).await
     }
 }
 
@@ -77,19 +481,277 @@ pub struct Post {
     // in the future; we won't be stuck with a parsed version
     pub content: String,
     // Some arbitrary data that could be used by the theme
-    pub theme_config: Option<serde_json::Value>
+    pub theme_config: Option<serde_json::Value>,
+    // Tags of the post, without duplicates
+    // Set by `tags` in the custom metadata
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // The UNIX timestamp (in seconds) when the post should go live
+    // Set only while the post is waiting to be published; the post
+    // is not accessible until then
+    #[serde(default)]
+    pub publish_at: Option<u64>,
+    // Pinned posts are shown on top of the first page of the home page
+    #[serde(default)]
+    pub pinned: bool,
+    // ID of the author in `authors` in config, if any
+    #[serde(default)]
+    pub author: Option<String>,
+    // Standalone pages are never listed with posts; see PagesList
+    #[serde(default)]
+    pub kind: PostKind,
+    // URLs the post had before `url`, oldest first
+    // They keep redirecting to `url`, until the post is deleted
+    #[serde(default)]
+    pub url_history: Vec<String>,
+    // Title of the series the post is part of, see Series
+    #[serde(default)]
+    pub series: Option<String>,
+    // Position in the series; posts without one come after those with one
+    #[serde(default)]
+    pub series_order: Option<i64>,
+    // Incremented on every write; see store::compare_and_put
+    // 0 for posts that have never been written
+    #[serde(default)]
+    pub version: u64
+}
+
+impl Versioned for Post {
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PostKind {
+    Post,
+    // "About", "Projects" and such, rendered with `page.hbs`
+    // Pages have no tags, series, pinning or scheduling
+    Page
+}
+
+impl Default for PostKind {
+    fn default() -> PostKind {
+        PostKind::Post
+    }
+}
+
+// Published standalone pages, stored as `pages_list`, in the order
+// they are shown in navigation (new pages are added to the end)
+// Like `posts_list`, this may be reordered via KV UI
+// Titles and URLs are kept here so that every page of the blog
+// can list them without loading the pages themselves
+#[derive(Serialize, Deserialize)]
+pub struct PagesList {
+    // Incremented on every write; see store::compare_and_put
+    #[serde(default)]
+    pub version: u64,
+    pub pages: Vec<PageEntry>
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct PageEntry {
+    pub uuid: String,
+    pub title: String,
+    pub url: String
+}
+
+impl Versioned for PagesList {
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+
+const PAGES_LIST_KEY: &'static str = "pages_list";
+
+impl PagesList {
+    pub async fn load() -> MyResult<PagesList> {
+        match store::try_get_str(PAGES_LIST_KEY).await? {
+            Some(raw) => serde_json::from_str(&raw).map_err(|e| Error::CorruptedData(
+                format!("`{}` cannot be decoded ({})", PAGES_LIST_KEY, e))),
+            None => Ok(PagesList { version: 0, pages: vec![] })
+        }
+    }
+
+    // Add, update or remove (with None) the entry of a page
+    // Nothing is written if the entry is already up to date, which
+    // is the case for every post that has never been a page
+    pub async fn sync_page(uuid: &str, page: Option<&Post>) -> MyResult<()> {
+        let entry = page.map(|p| PageEntry {
+            uuid: p.uuid.clone(),
+            title: p.title.clone(),
+            url: p.url.clone()
+        });
+        for _ in 0..store::MAX_CAS_ATTEMPTS {
+            let mut list = Self::load().await?;
+            let pos = list.pages.iter().position(|p| p.uuid == uuid);
+            match (pos, &entry) {
+                (Some(pos), Some(entry)) if list.pages[pos] == *entry => return Ok(()),
+                (Some(pos), Some(entry)) => list.pages[pos] = entry.clone(),
+                (None, Some(entry)) => list.pages.push(entry.clone()),
+                (Some(pos), None) => {
+                    list.pages.remove(pos);
+                },
+                (None, None) => return Ok(())
+            }
+            if store::compare_and_put(PAGES_LIST_KEY, &mut list, true).await? {
+                return Ok(());
+            }
+        }
+
+        Err(Error::Conflict(format!("`{}` is being updated concurrently, please try again", PAGES_LIST_KEY)))
+    }
+}
+
+// Published posts in a series, stored as `series_<title>`
+// Everything needed to link to the other parts is kept here,
+// so that rendering a post only needs this one extra read
+#[derive(Serialize, Deserialize)]
+pub struct Series {
+    // Incremented on every write; see store::compare_and_put
+    #[serde(default)]
+    pub version: u64,
+    pub title: String,
+    // Sorted by `order`, then by `timestamp`
+    pub posts: Vec<SeriesEntry>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SeriesEntry {
+    pub uuid: String,
+    pub title: String,
+    pub url: String,
+    pub order: Option<i64>,
+    pub timestamp: u64
+}
+
+impl Versioned for Series {
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+
+pub const SERIES_KEY_PREFIX: &'static str = "series_";
+
+impl Series {
+    fn title_to_key(title: &str) -> String {
+        format!("{}{}", SERIES_KEY_PREFIX, title)
+    }
+
+    // A series nothing has been published in is just empty
+    pub async fn load(title: &str) -> MyResult<Series> {
+        match store::try_get_str(&Self::title_to_key(title)).await? {
+            Some(raw) => serde_json::from_str(&raw).map_err(|e| Error::CorruptedData(
+                format!("`{}` cannot be decoded ({})", Self::title_to_key(title), e))),
+            None => Ok(Series { version: 0, title: title.into(), posts: vec![] })
+        }
+    }
+
+    async fn update<F: FnMut(&mut Vec<SeriesEntry>)>(title: &str, mut f: F) -> MyResult<()> {
+        for _ in 0..store::MAX_CAS_ATTEMPTS {
+            let mut series = Self::load(title).await?;
+            f(&mut series.posts);
+            series.posts.sort_by_key(|p| (p.order.is_none(), p.order, p.timestamp));
+            if store::compare_and_put(&Self::title_to_key(title), &mut series, false).await? {
+                return Ok(());
+            }
+        }
+
+        Err(Error::Conflict(format!("`{}` is being updated concurrently, please try again",
+            Self::title_to_key(title))))
+    }
+
+    // Bring the series indices in line with a post that was in `old_series`
+    // Pass None as `post` if the post is no longer published
+    pub async fn sync_post(uuid: &str, old_series: Option<&str>, post: Option<&Post>) -> MyResult<()> {
+        let new_series = post.and_then(|p| p.series.as_ref());
+        if let Some(old_series) = old_series {
+            if new_series.map(|s| s.as_str()) != Some(old_series) {
+                Self::update(old_series, |posts| posts.retain(|p| p.uuid != uuid)).await?;
+            }
+        }
+
+        if let (Some(post), Some(new_series)) = (post, new_series) {
+            let entry = SeriesEntry {
+                uuid: post.uuid.clone(),
+                title: post.title.clone(),
+                url: post.url.clone(),
+                order: post.series_order,
+                timestamp: post.timestamp
+            };
+            Self::update(new_series, |posts| {
+                posts.retain(|p| p.uuid != uuid);
+                posts.push(entry.clone());
+            }).await?;
+        }
+        Ok(())
+    }
+}
+
+// Prefixes of the keys of per-post records
+// These can be used with `store::list` to walk through all of them
+pub const POST_KEY_PREFIX: &'static str = "post_by_uuid_";
+pub const URL_MAPPING_KEY_PREFIX: &'static str = "url_mapping_";
+// URLs of deleted posts, mapped to the UUID of the post they belonged to
+pub const URL_TOMBSTONE_KEY_PREFIX: &'static str = "url_tombstone_";
+pub const CONTENT_CACHE_KEY_PREFIX: &'static str = "content_cache_";
+pub const CACHE_WHITELIST_KEY_PREFIX: &'static str = "cache_whitelist_";
+// Followed by `<uuid>_<revision>`, see PostRevision
+pub const REVISION_KEY_PREFIX: &'static str = "post_revision_";
+
+// A previous version of a post
+// Whenever a post is overwritten, the version being replaced is kept
+// as a revision, up to `revisions_to_keep` in config per post
+#[derive(Serialize, Deserialize)]
+pub struct PostRevision {
+    // The `version` of the post kept in this revision
+    pub revision: u64,
+    // The UNIX timestamp (in seconds) when it was replaced
+    pub saved_at: u64,
+    pub post: Post
 }
 
 impl Post {
     fn uuid_to_post_key(uuid: &str) -> String {
-        format!("post_by_uuid_{}", uuid)
+        format!("{}{}", POST_KEY_PREFIX, uuid)
     }
 
     fn url_to_mapping_key(url: &str) -> String {
-        format!("url_mapping_{}", url)
+        format!("{}{}", URL_MAPPING_KEY_PREFIX, url)
+    }
+
+    fn url_to_tombstone_key(url: &str) -> String {
+        format!("{}{}", URL_TOMBSTONE_KEY_PREFIX, url)
+    }
+
+    fn revision_key_prefix(uuid: &str) -> String {
+        format!("{}{}_", REVISION_KEY_PREFIX, uuid)
+    }
+
+    // Revisions are zero-padded so that listing returns them in order
+    fn revision_key(uuid: &str, revision: u64) -> String {
+        format!("{}{:020}", Self::revision_key_prefix(uuid), revision)
     }
 
-    async fn create_url_mapping(url: &str, uuid: &str) -> MyResult<()> {
+    // A URL of a deleted post can be taken by a new one
+    pub async fn create_url_mapping(url: &str, uuid: &str) -> MyResult<()> {
+        let tombstone_key = Self::url_to_tombstone_key(url);
+        if store::try_get_str(&tombstone_key).await?.is_some() {
+            store::delete(&tombstone_key).await?;
+        }
         store::put_str(&Self::url_to_mapping_key(url), uuid).await
     }
 
@@ -105,9 +767,54 @@ impl Post {
         store::get_obj(&Self::uuid_to_post_key(uuid)).await
     }
 
+    // Posts scheduled for later are hidden until their time comes,
+    // even if the scheduler hasn't got to them yet
+    pub fn is_pending(&self) -> bool {
+        match self.publish_at {
+            Some(publish_at) => publish_at > Date::now() as u64 / 1000,
+            None => false
+        }
+    }
+
+    // Returns Err(Gone) if the URL belonged to a post that has been deleted
     pub async fn find_by_url(url: &str) -> MyResult<Post> {
-        let uuid = store::get_str(&Self::url_to_mapping_key(url)).await?;
-        Self::find_by_uuid(&uuid).await
+        let gone = || Error::Gone("This post has been deleted".into());
+        match store::try_get_str(&Self::url_to_mapping_key(url)).await? {
+            // Mappings of posts deleted before tombstones were introduced
+            // are left dangling
+            Some(uuid) => match store::try_get_str(&Self::uuid_to_post_key(&uuid)).await? {
+                Some(raw) => serde_json::from_str(&raw).internal_err(),
+                None => Err(gone())
+            },
+            None => match store::try_get_str(&Self::url_to_tombstone_key(url)).await? {
+                Some(_) => Err(gone()),
+                None => Err(Error::NotFound("This page is not available".into()))
+            }
+        }
+    }
+
+    // UUID of the post currently living at `url`, if any
+    // Old URLs of posts (which only redirect) and URLs of deleted posts don't count
+    pub async fn find_url_owner(url: &str) -> MyResult<Option<String>> {
+        match Self::find_by_url(url).await {
+            Ok(post) if post.url == url => Ok(Some(post.uuid)),
+            Ok(_) | Err(Error::NotFound(_)) | Err(Error::Gone(_)) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    // Carry over the URL history of the version being replaced, and
+    // record its URL if it's changing, so that no URL the post has
+    // ever had is forgotten (e.g. when rolling back to a revision)
+    fn update_url_history(&mut self, previous: &Post) {
+        let mut history = previous.url_history.clone();
+        for url in self.url_history.iter().chain(std::iter::once(&previous.url)) {
+            if !history.contains(url) {
+                history.push(url.clone());
+            }
+        }
+        history.retain(|u| u != &self.url);
+        self.url_history = history;
     }
 
     // Write the Post to KV storage; this can be a new post or
@@ -115,12 +822,88 @@ impl Post {
     // responsible for making sure PostsList is updated with the
     // latest set of posts sorted in order.
     // This function will also create a mapping from URL to UUID in the KV
-    pub async fn write_to_kv(self) -> MyResult<()> {
+    // Returns Err(Conflict) if the post has been written by someone else
+    // since it was loaded (or, for new posts, if it was created in the meantime);
+    // the caller should then load it again and re-apply its changes.
+    // The version being replaced is kept as a revision.
+    pub async fn write_to_kv(&mut self) -> MyResult<()> {
+        let key = Self::uuid_to_post_key(&self.uuid);
+        let previous: Option<Post> = match store::try_get_str(&key).await? {
+            Some(raw) => serde_json::from_str(&raw).ok(),
+            None => None
+        };
+
+        if let Some(previous) = previous.as_ref() {
+            self.update_url_history(previous);
+        }
         Self::create_url_mapping(&self.url, &self.uuid).await?;
-        store::put_obj(&Self::uuid_to_post_key(&self.uuid), self).await
+        if !store::compare_and_put(&key, self, false).await? {
+            return Err(Error::Conflict(format!("Post {} has been updated concurrently", self.uuid)));
+        }
+
+        // Only if it's really the version we have just replaced
+        if let Some(previous) = previous {
+            if previous.version + 1 == self.version {
+                Self::save_revision(previous).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn save_revision(post: Post) -> MyResult<()> {
+        if crate::CONFIG.revisions_to_keep == 0 {
+            return Ok(());
+        }
+
+        let uuid = post.uuid.clone();
+        let revision = PostRevision {
+            revision: post.version,
+            saved_at: Date::now() as u64 / 1000,
+            post
+        };
+        store::put_obj(&Self::revision_key(&uuid, revision.revision), revision).await?;
+
+        // Drop the oldest ones
+        let revisions = Self::list_revisions(&uuid).await?;
+        if revisions.len() > crate::CONFIG.revisions_to_keep {
+            for revision in revisions[..revisions.len() - crate::CONFIG.revisions_to_keep].iter() {
+                store::delete(&Self::revision_key(&uuid, *revision)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Revision numbers of the post, oldest first
+    pub async fn list_revisions(uuid: &str) -> MyResult<Vec<u64>> {
+        Ok(store::list_all_stripped(&Self::revision_key_prefix(uuid)).await?.into_iter()
+            .filter_map(|r| r.parse().ok())
+            .collect())
+    }
+
+    pub async fn find_revision(uuid: &str, revision: u64) -> MyResult<PostRevision> {
+        match store::try_get_str(&Self::revision_key(uuid, revision)).await? {
+            Some(raw) => serde_json::from_str(&raw).internal_err(),
+            None => Err(Error::NotFound(format!("Revision {} of post {} does not exist", revision, uuid)))
+        }
     }
 
+    // Also deletes all revisions and the neighbour pointers of the post,
+    // and replaces the mappings of all of its URLs with tombstones
+    // URLs taken over by other posts in the meantime are left alone
     pub async fn delete_by_uuid(uuid: &str) -> MyResult<()> {
+        if let Ok(post) = Self::find_by_uuid(uuid).await {
+            for url in post.url_history.iter().chain(std::iter::once(&post.url)) {
+                let mapping_key = Self::url_to_mapping_key(url);
+                if store::try_get_str(&mapping_key).await?.as_deref() == Some(uuid) {
+                    store::put_str(&Self::url_to_tombstone_key(url), uuid).await?;
+                    store::delete(&mapping_key).await?;
+                }
+            }
+        }
+        for revision in Self::list_revisions(uuid).await? {
+            store::delete(&Self::revision_key(uuid, revision)).await?;
+        }
+        PostNeighbours::delete_by_uuid(uuid).await?;
         store::delete(&Self::uuid_to_post_key(uuid)).await
     }
 }
@@ -169,16 +952,271 @@ pub struct PostContentCache {
     // show up in the rendered result.
     pub summary: String,
     // Compiled content in HTML
-    pub content: String
+    pub content: String,
+    // Words in the post, not counting code blocks
+    // Each Chinese / Japanese character counts as a word
+    #[serde(default)]
+    pub word_count: usize,
+    // Estimated time to read the post, in minutes (at least 1)
+    #[serde(default)]
+    pub reading_time: u64,
+    // Headings of the post, nested by level
+    #[serde(default)]
+    pub toc: Vec<TocEntry>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TocEntry {
+    // 1 to 6, as in <h1> to <h6>
+    pub level: u32,
+    // Plain text of the heading
+    pub title: String,
+    // `id` of the heading, without the `#`
+    pub anchor: String,
+    // Following headings of a deeper level
+    pub children: Vec<TocEntry>
+}
+
+impl TocEntry {
+    // Nest a flat list of headings in document order
+    // A heading becomes a child of the closest preceding heading of a
+    // lower level; if there is none, it stays at the top level
+    fn nest(flat: Vec<TocEntry>) -> Vec<TocEntry> {
+        let mut root: Vec<TocEntry> = vec![];
+        // Headings whose children are still being collected, deepest last
+        let mut open: Vec<TocEntry> = vec![];
+        let close = |entry: TocEntry, open: &mut Vec<TocEntry>, root: &mut Vec<TocEntry>| {
+            match open.last_mut() {
+                Some(parent) => parent.children.push(entry),
+                None => root.push(entry)
+            }
+        };
+        for entry in flat {
+            while open.last().map_or(false, |last| last.level >= entry.level) {
+                let done = open.pop().unwrap();
+                close(done, &mut open, &mut root);
+            }
+            open.push(entry);
+        }
+        while let Some(done) = open.pop() {
+            close(done, &mut open, &mut root);
+        }
+        root
+    }
+}
+
+// The `id` given to a heading from its plain text: lowercase letters and
+// digits of any script, words joined by `-`, other punctuation dropped
+// e.g. "Hello, World!" -> "hello-world", "你好 世界" -> "你好-世界"
+fn heading_slug(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    match slug.trim_end_matches('-') {
+        // e.g. headings made of emoji only
+        "" => "section".into(),
+        slug => slug.into()
+    }
+}
+
+// Start of the section footnotes are collected into
+// Everything from here on is dropped from the summary
+const FOOTNOTES_SECTION_START: &'static str = "<section class=\"footnotes\">";
+
+// Footnote labels are case-insensitive, as link labels are
+fn footnote_key(label: &str) -> String {
+    label.to_lowercase()
+}
+
+// The `id` of a footnote is made from its label rather than its number,
+// so that links to it stay valid when other footnotes are added
+fn footnote_id(key: &str) -> String {
+    escape_html_attr(&key.split_whitespace().collect::<Vec<_>>().join("-"))
+}
+
+// Footnotes of a post, numbered in the order they are first referenced
+struct Footnotes<'ev> {
+    // Events inside each definition, taken out of the text
+    definitions: HashMap<String, Vec<Event<'ev>>>,
+    defined: HashSet<String>,
+    // Keys of referenced footnotes; a footnote's number is its position + 1
+    order: Vec<String>,
+    // How many times each footnote has been referenced
+    refs: HashMap<String, usize>,
+    // HTML of every reference, to be stripped from the summary
+    markers: Vec<String>
+}
+
+impl<'ev> Footnotes<'ev> {
+    fn reference(&mut self, label: &str) -> Event<'ev> {
+        let key = footnote_key(label);
+        if !self.defined.contains(&key) {
+            // Left as written, like a reference link without a definition
+            return Event::Text(format!("[^{}]", label).into());
+        }
+        if !self.refs.contains_key(&key) {
+            self.order.push(key.clone());
+        }
+        let count = self.refs.entry(key.clone()).or_insert(0);
+        *count += 1;
+        let number = self.order.iter().position(|k| *k == key).unwrap() + 1;
+        let id = footnote_id(&key);
+        let html = format!(
+            "<sup class=\"footnote-reference\" id=\"{}\"><a href=\"#fn-{}\">{}</a></sup>",
+            footnote_ref_id(&id, *count), id, number);
+        self.markers.push(html.clone());
+        Event::Html(html.into())
+    }
+
+    // Links back to every reference of a footnote
+    fn backlinks(&self, key: &str) -> String {
+        let id = footnote_id(key);
+        let count = self.refs.get(key).cloned().unwrap_or(0);
+        (1..=count).map(|n| format!(
+            // U+FE0E keeps the arrow from being shown as an emoji
+            "<a href=\"#{}\" class=\"footnote-backref\">\u{21a9}\u{fe0e}{}</a>",
+            footnote_ref_id(&id, n),
+            if n == 1 { "".into() } else { format!("<sup>{}</sup>", n) }
+        )).collect::<Vec<_>>().join(" ")
+    }
+}
+
+// The `id` of the n-th reference to a footnote, counting from 1
+fn footnote_ref_id(id: &str, n: usize) -> String {
+    if n == 1 {
+        format!("fnref-{}", id)
+    } else {
+        format!("fnref-{}-{}", id, n)
+    }
+}
+
+// Find `$...$` and `$$...$$` in Markdown source, returning the range of each
+// (delimiters included) and whether it is display math
+// As in Pandoc, `$` only opens inline math when not followed by a space, and
+// only closes it when not preceded by a space or followed by a digit,
+// so that "$5 and $10" is left alone. `\$` is never a delimiter.
+#[cfg(katex)]
+fn find_math(raw: &str) -> Vec<(std::ops::Range<usize>, bool)> {
+    let bytes = raw.as_bytes();
+    let mut ret = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'$' => {
+                let display = bytes.get(i + 1) == Some(&b'$');
+                let from = if display { i + 2 } else { i + 1 };
+                match find_math_end(bytes, from, display) {
+                    Some(end) => {
+                        ret.push((i..end, display));
+                        i = end;
+                    },
+                    None => i = from
+                }
+            },
+            _ => i += 1
+        }
+    }
+    ret
+}
+
+// Where the math starting at `from` ends, after its closing delimiter
+#[cfg(katex)]
+fn find_math_end(bytes: &[u8], from: usize, display: bool) -> Option<usize> {
+    if !display && bytes.get(from).map_or(true, |b| b.is_ascii_whitespace()) {
+        return None;
+    }
+    let mut i = from;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'$' if display => {
+                if bytes.get(i + 1) == Some(&b'$') {
+                    return if i > from { Some(i + 2) } else { None };
+                }
+                i += 1;
+            },
+            b'$' => {
+                let closes = !bytes[i - 1].is_ascii_whitespace()
+                    && !bytes.get(i + 1).map_or(false, |b| b.is_ascii_digit());
+                return if closes { Some(i + 1) } else { None };
+            },
+            _ => i += 1
+        }
+    }
+    None
+}
+
+// Reading speeds used for `reading_time`
+const WORDS_PER_MINUTE: f64 = 200.0;
+// Chinese and Japanese have no spaces between words,
+// so characters are counted instead
+const CJK_CHARS_PER_MINUTE: f64 = 400.0;
+
+fn is_cjk(c: char) -> bool {
+    match c as u32 {
+        0x3040..=0x30FF // Hiragana, Katakana
+        | 0x31F0..=0x31FF // Katakana phonetic extensions
+        | 0x3400..=0x4DBF // CJK Unified Ideographs Extension A
+        | 0x4E00..=0x9FFF // CJK Unified Ideographs
+        | 0xF900..=0xFAFF // CJK Compatibility Ideographs
+        | 0xFF66..=0xFF9F // Halfwidth Katakana
+        | 0x20000..=0x2FA1F => true, // Supplementary ideographs
+        _ => false
+    }
+}
+
+#[derive(Default)]
+struct TextStats {
+    words: usize,
+    cjk_chars: usize,
+    // Whether the current word has a letter or digit in it
+    in_word: bool
+}
+
+impl TextStats {
+    // A word is a run of non-space, non-CJK characters with at least one
+    // letter or digit in it, so that "don't" is one word and "--" is none
+    // Words may span several text events (e.g. `**foo**bar` or smart quotes)
+    fn count(&mut self, text: &str) {
+        for c in text.chars() {
+            if c.is_whitespace() || is_cjk(c) {
+                self.end_word();
+                if is_cjk(c) {
+                    self.cjk_chars += 1;
+                }
+            } else if c.is_alphanumeric() {
+                self.in_word = true;
+            }
+        }
+    }
+
+    fn end_word(&mut self) {
+        if self.in_word {
+            self.words += 1;
+        }
+        self.in_word = false;
+    }
+
+    fn reading_time(&self) -> u64 {
+        let minutes = self.words as f64 / WORDS_PER_MINUTE
+            + self.cjk_chars as f64 / CJK_CHARS_PER_MINUTE;
+        std::cmp::max(minutes.ceil() as u64, 1)
+    }
 }
 
 impl PostContentCache {
     fn uuid_to_cache_key(uuid: &str) -> String {
-        format!("content_cache_{}", uuid)
+        format!("{}{}", CONTENT_CACHE_KEY_PREFIX, uuid)
     }
 
     fn url_to_cache_whitelist_key(url: &str) -> String {
-        format!("cache_whitelist_{}", url)
+        format!("{}{}", CACHE_WHITELIST_KEY_PREFIX, url)
     }
 
     pub async fn is_external_url_whitelisted_for_cache(url: &str) -> bool {
@@ -194,6 +1232,10 @@ impl PostContentCache {
         }
     }
 
+    pub async fn whitelist_external_url_for_cache(url: &str) -> MyResult<()> {
+        store::put_str(&Self::url_to_cache_whitelist_key(url), "Y").await
+    }
+
     async fn find_by_uuid(uuid: &str) -> MyResult<PostContentCache> {
         store::get_obj(&Self::uuid_to_cache_key(uuid)).await
     }
@@ -229,10 +1271,10 @@ impl PostContentCache {
                 // because even if it breaks we still can recover by a simple refresh
                 // and once it's written, it's permanent, so we expect the write
                 // to succeed as soon as the article is submitted
-                let url_cache_key = Self::url_to_cache_whitelist_key(url);
+                let url_to_whitelist = url.to_string();
                 crate::EVENT.with(move |ev| {
                     ev.wait_until(&future_to_promise(async move {
-                        let _ = store::put_str(&url_cache_key, "Y").await;
+                        let _ = Self::whitelist_external_url_for_cache(&url_to_whitelist).await;
                         Ok(JsValue::TRUE)
                     })).unwrap();
                 });
@@ -257,6 +1299,240 @@ impl PostContentCache {
         })
     }
 
+    // Give every heading an `id`, and collect them for the table of contents
+    // The `id` comes from `{#custom-id}` at the end of the heading if present,
+    // and from its plain text otherwise; repeated ones get `-1`, `-2`, ...
+    fn transform_headings<'ev>(events: Vec<Event<'ev>>) -> (Vec<Event<'ev>>, Vec<TocEntry>) {
+        let mut ret = Vec::with_capacity(events.len());
+        let mut headings = vec![];
+        let mut used_ids = HashSet::new();
+        // Events inside the heading being read
+        let mut heading: Option<Vec<Event<'ev>>> = None;
+        for ev in events {
+            match ev {
+                Event::Start(Tag::Heading(_)) => heading = Some(vec![]),
+                Event::End(Tag::Heading(level)) => {
+                    let mut inner = Self::merge_text(heading.take().unwrap_or_default());
+                    let custom_id = Self::take_custom_id(&mut inner);
+                    let title: String = inner.iter().filter_map(|ev| match ev {
+                        Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                        _ => None
+                    }).collect();
+                    let base = custom_id.unwrap_or_else(|| heading_slug(&title));
+                    let mut id = base.clone();
+                    let mut n = 0;
+                    while !used_ids.insert(id.clone()) {
+                        n += 1;
+                        id = format!("{}-{}", base, n);
+                    }
+
+                    ret.push(Event::Html(format!("<h{} id=\"{}\">", level, escape_html_attr(&id)).into()));
+                    ret.extend(inner);
+                    ret.push(Event::Html(format!("</h{}>\n", level).into()));
+                    headings.push(TocEntry {
+                        level,
+                        title: title.trim().into(),
+                        anchor: id,
+                        children: vec![]
+                    });
+                },
+                ev => match heading.as_mut() {
+                    Some(inner) => inner.push(ev),
+                    None => ret.push(ev)
+                }
+            }
+        }
+        (ret, headings)
+    }
+
+    // Typeset `$...$` and `$$...$$` with KaTeX
+    // Math is looked for in text outside of code, and taken from the Markdown
+    // source as written, since escapes like `\\` and `\{` mean something to TeX
+    // Math that contains Markdown formatting (e.g. `*a*`) is not recognized
+    #[cfg(katex)]
+    fn transform_math<'ev>(
+        source: &'ev str, events: Vec<(Event<'ev>, std::ops::Range<usize>)>
+    ) -> Vec<Event<'ev>> {
+        let mut ret = Vec::with_capacity(events.len());
+        // Consecutive text, which math can span
+        let mut run = vec![];
+        let mut in_code_block = false;
+        for (ev, range) in events {
+            match ev {
+                Event::Text(_) | Event::SoftBreak if !in_code_block => {
+                    run.push((ev, range));
+                    continue;
+                },
+                Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+                Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+                _ => ()
+            }
+            Self::typeset_math(source, std::mem::take(&mut run), &mut ret);
+            ret.push(ev);
+        }
+        Self::typeset_math(source, run, &mut ret);
+        ret
+    }
+
+    #[cfg(katex)]
+    fn typeset_math<'ev>(
+        source: &'ev str, run: Vec<(Event<'ev>, std::ops::Range<usize>)>, out: &mut Vec<Event<'ev>>
+    ) {
+        let (start, end) = match (run.first(), run.last()) {
+            (Some((_, first)), Some((_, last))) => (first.start, last.end),
+            _ => return
+        };
+        let mut pos = start;
+        for (span, display) in find_math(&source[start..end]) {
+            let span = span.start + start..span.end + start;
+            Self::push_text_between(source, &run, pos..span.start, out);
+            let delim = if display { 2 } else { 1 };
+            match crate::katex::render(&source[span.start + delim..span.end - delim], display) {
+                Some(html) => out.push(Event::Html(html.into())),
+                None => out.push(Event::Text(source[span.clone()].into()))
+            }
+            pos = span.end;
+        }
+        Self::push_text_between(source, &run, pos..end, out);
+    }
+
+    // Text outside of math is taken from the events, so that
+    // escapes and entities there work as usual
+    #[cfg(katex)]
+    fn push_text_between<'ev>(
+        source: &'ev str, run: &[(Event<'ev>, std::ops::Range<usize>)],
+        range: std::ops::Range<usize>, out: &mut Vec<Event<'ev>>
+    ) {
+        for (ev, ev_range) in run {
+            let (start, end) = (ev_range.start.max(range.start), ev_range.end.min(range.end));
+            if start >= end {
+                continue;
+            }
+            if start == ev_range.start && end == ev_range.end {
+                out.push(ev.clone());
+            } else {
+                // Only plain text can be cut by a delimiter, since neither
+                // escapes nor entities produce `$` in the source
+                out.push(Event::Text(source[start..end].into()));
+            }
+        }
+    }
+
+    // Move footnote definitions into a numbered list at the end of the post,
+    // each with links back to where it is referenced
+    // Definitions that are never referenced are dropped
+    // Also returns the HTML of every reference, for stripping from the summary
+    fn transform_footnotes<'ev>(events: Vec<Event<'ev>>) -> (Vec<Event<'ev>>, Vec<String>) {
+        let mut footnotes = Footnotes {
+            definitions: HashMap::new(),
+            defined: HashSet::new(),
+            order: vec![],
+            refs: HashMap::new(),
+            markers: vec![]
+        };
+
+        // Definitions have to be found first, since they may come after references
+        let mut body = Vec::with_capacity(events.len());
+        let mut definition: Option<(String, Vec<Event<'ev>>)> = None;
+        for ev in events {
+            match ev {
+                Event::Start(Tag::FootnoteDefinition(label)) =>
+                    definition = Some((footnote_key(&label), vec![])),
+                Event::End(Tag::FootnoteDefinition(_)) => if let Some((key, inner)) = definition.take() {
+                    // The first definition of a label wins
+                    if footnotes.defined.insert(key.clone()) {
+                        footnotes.definitions.insert(key, inner);
+                    }
+                },
+                ev => match definition.as_mut() {
+                    Some((_, inner)) => inner.push(ev),
+                    None => body.push(ev)
+                }
+            }
+        }
+
+        let mut ret: Vec<Event<'ev>> = body.into_iter().map(|ev| match ev {
+            Event::FootnoteReference(label) => footnotes.reference(&label),
+            ev => ev
+        }).collect();
+        if footnotes.order.is_empty() {
+            return (ret, footnotes.markers);
+        }
+
+        // Footnotes may reference other footnotes, numbering more of them as we go
+        let mut rendered = vec![];
+        let mut i = 0;
+        while i < footnotes.order.len() {
+            let key = footnotes.order[i].clone();
+            let inner = footnotes.definitions.remove(&key).unwrap_or_default();
+            let inner: Vec<Event<'ev>> = inner.into_iter().map(|ev| match ev {
+                Event::FootnoteReference(label) => footnotes.reference(&label),
+                ev => ev
+            }).collect();
+            rendered.push((key, inner));
+            i += 1;
+        }
+
+        ret.push(Event::Html(format!("{}\n<ol>\n", FOOTNOTES_SECTION_START).into()));
+        for (key, mut inner) in rendered {
+            let backlinks = footnotes.backlinks(&key);
+            ret.push(Event::Html(format!("<li id=\"fn-{}\">\n", footnote_id(&key)).into()));
+            // Keep the links in the last paragraph instead of on a line of their own
+            match inner.last() {
+                Some(Event::End(Tag::Paragraph)) => {
+                    let end = inner.pop().unwrap();
+                    inner.push(Event::Html(format!(" {}", backlinks).into()));
+                    inner.push(end);
+                },
+                _ => inner.push(Event::Html(format!("<p>{}</p>\n", backlinks).into()))
+            }
+            ret.extend(inner);
+            ret.push(Event::Html("</li>\n".into()));
+        }
+        ret.push(Event::Html("</ol>\n</section>\n".into()));
+        (ret, footnotes.markers)
+    }
+
+    // The parser may split text at characters like `_`,
+    // which would get in the way of finding `{#custom-id}`
+    fn merge_text<'ev>(events: Vec<Event<'ev>>) -> Vec<Event<'ev>> {
+        let mut ret: Vec<Event<'ev>> = Vec::with_capacity(events.len());
+        for ev in events {
+            match (ret.last_mut(), ev) {
+                (Some(Event::Text(prev)), Event::Text(text)) => {
+                    *prev = format!("{}{}", prev, text).into();
+                },
+                (_, ev) => ret.push(ev)
+            }
+        }
+        ret
+    }
+
+    // Remove `{#custom-id}` from the end of a heading, returning the ID
+    fn take_custom_id(inner: &mut Vec<Event>) -> Option<String> {
+        let (id, rest) = match inner.last() {
+            Some(Event::Text(text)) => {
+                let text = text.trim_end();
+                let start = text.rfind("{#")?;
+                if !text.ends_with('}') {
+                    return None;
+                }
+                let id = text[start + 2..text.len() - 1].trim();
+                if id.is_empty() || id.contains(char::is_whitespace) {
+                    return None;
+                }
+                (id.to_owned(), text[..start].trim_end().to_owned())
+            },
+            _ => return None
+        };
+
+        inner.pop();
+        if !rest.is_empty() {
+            inner.push(Event::Text(rest.into()));
+        }
+        Some(id)
+    }
+
     fn transform_code_block_highlight<'ev>(
         parser: impl Iterator<Item = Event<'ev>>
     ) -> impl Iterator<Item = Event<'ev>> {
@@ -300,21 +1576,6 @@ impl PostContentCache {
     fn transform_html(html: String) -> String {
         let js_html: JsString = html.into();
 
-        // Add `id="xxx"` to all headings for anchoring
-        // Replacing is done in a Closure in order to generate
-        // the proper anchor string for each heading
-        // This matches only a single line, which is good because
-        // we only want it to match a single heading tag
-        // If it matched multiple lines, then it may match the
-        // ending tag of another heading.
-        let regex_heading = RegExp::new(r"<h(\d)>([^<]*)<\/h\1>", "ig");
-        let closure = Closure::wrap(Box::new(|_m: String, p1: String, p2: String| {
-            let anchor = filter_non_ascii_alphanumeric(
-                &p2.to_lowercase()).replace(" ", "-");
-            format!("<h{} id=\"{}\">{}</h{}>", p1, anchor, p2, p1)
-        }) as Box<dyn Fn(String, String, String) -> String>);
-        let js_html = js_html.replace_by_pattern_with_function(&regex_heading, closure.as_ref().unchecked_ref());
-
         // Transform all <pre><code> to <pre><code class="hljs">
         // For syntax highlighting
         // We don't match the end tag because it may span multiple lines
@@ -336,6 +1597,35 @@ impl PostContentCache {
     // async only comes from digesting via SubtleCrypto
     pub async fn render(post: &Post) -> PostContentCache {
         let parser = Parser::new_ext(&post.content, Options::all());
+        // Typeset math via KaTeX, if built in
+        #[cfg(katex)]
+        let events = Self::transform_math(&post.content, parser.into_offset_iter().collect());
+        #[cfg(not(katex))]
+        let events = parser.collect();
+        // Anchor headings, collecting them for the table of contents
+        let (events, headings) = Self::transform_headings(events);
+        // Collect footnotes at the end, with links back to their references
+        let (events, footnote_markers) = Self::transform_footnotes(events);
+        let parser = events.into_iter();
+        // Count words before code blocks are turned into HTML
+        let mut stats = TextStats::default();
+        let mut in_code_block = false;
+        let parser = parser.inspect(|ev| match ev {
+            Event::Text(text) | Event::Code(text) if !in_code_block => stats.count(text),
+            // Inline formatting doesn't end words
+            Event::Start(Tag::Emphasis) | Event::End(Tag::Emphasis)
+                | Event::Start(Tag::Strong) | Event::End(Tag::Strong)
+                | Event::Start(Tag::Strikethrough) | Event::End(Tag::Strikethrough)
+                | Event::Start(Tag::Link(..)) | Event::End(Tag::Link(..)) => (),
+            _ => {
+                stats.end_word();
+                match ev {
+                    Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+                    Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+                    _ => ()
+                }
+            }
+        });
         // Apply code highlighting via Highlight.js
         let parser = Self::transform_code_block_highlight(parser);
         // Apply tag transform
@@ -343,16 +1633,31 @@ impl PostContentCache {
 
         let mut html_output = String::new();
         html::push_html(&mut html_output, parser);
+        stats.end_word();
         html_output = Self::transform_html(html_output);
+        let mut summary = match html_output.find(SUMMARY_DIVIDER) {
+            None => html_output.clone(),
+            Some(x) => (&html_output[0..x]).to_owned()
+        };
+        // Footnotes only work on the post page, so they are left out of
+        // the summary shown on the home page and in RSS
+        if !footnote_markers.is_empty() {
+            if let Some(x) = summary.rfind(FOOTNOTES_SECTION_START) {
+                summary.truncate(x);
+            }
+            for marker in footnote_markers.iter() {
+                summary = summary.replace(marker, "");
+            }
+        }
         PostContentCache {
             uuid: post.uuid.clone(),
             version: CACHE_VERSION.to_owned(),
             orig_digest: crate::utils::sha1(&post.content).await,
-            summary: match html_output.find(SUMMARY_DIVIDER) {
-                None => html_output.clone(),
-                Some(x) => (&html_output[0..x]).to_owned()
-            },
-            content: html_output
+            summary,
+            content: html_output,
+            word_count: stats.words + stats.cjk_chars,
+            reading_time: stats.reading_time(),
+            toc: TocEntry::nest(headings)
         }
     }
 
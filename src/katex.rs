@@ -0,0 +1,16 @@
+// Simple bindings for KaTeX
+// Like Highlight.js, math is typeset when the post is rendered
+// instead of on client. Only built in with `"katex": true`
+// in config.json (see build.rs)
+use js_sys::{Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+include!(concat!(env!("OUT_DIR"), "/load_katex.rs"));
+
+pub fn render(tex: &str, display: bool) -> Option<String> {
+    let options = Object::new();
+    Reflect::set(&options, &"displayMode".into(), &display.into()).unwrap();
+    // Show errors in the typeset math instead of throwing
+    Reflect::set(&options, &"throwOnError".into(), &false.into()).unwrap();
+    katex_render_to_string(tex, &options).ok()?.as_string()
+}
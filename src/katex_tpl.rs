@@ -0,0 +1,5 @@
+// Will be loaded by build.rs and add #[wasm_bindgen(inline_js = "...")] here
+extern "C" {
+    #[wasm_bindgen(catch, js_name = "renderToString")]
+    fn katex_render_to_string(tex: &str, options: &JsValue) -> Result<JsValue, JsValue>;
+}
\ No newline at end of file
@@ -9,11 +9,20 @@ mod task;
 #[macro_use]
 mod utils;
 mod router;
-mod store;
+pub mod store;
 mod hljs;
+#[cfg(katex)]
+mod katex;
 mod blog;
 mod sn;
 mod render;
+mod backup;
+mod media;
+mod revisions;
+mod schedule;
+mod admin;
+#[cfg(test)]
+mod tests;
 
 use cfg_if::cfg_if;
 use js_sys::{Promise};
@@ -22,6 +31,8 @@ use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::*;
 
+pub use utils::{Error, MyResult};
+
 cfg_if! {
     // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
     // allocator.
@@ -37,20 +48,46 @@ lazy_static! {
         build_routes()
     };
 
-    pub static ref CONFIG: utils::Config = {
-        serde_json::from_str(std::include_str!("../config.json")).unwrap()
-    };
+    static ref FILE_CONFIG: utils::Config = load_config();
 
     pub static ref CACHE_CONTROL_STATIC_FILE: String = {
         format!("max-age={}", CONFIG.cache_maxage)
     };
 }
 
+// The configuration of the blog, from config.json
+// Tests can replace it for the rest of a test with `tests::override_config`
+pub static CONFIG: ConfigRef = ConfigRef;
+
+pub struct ConfigRef;
+
+impl std::ops::Deref for ConfigRef {
+    type Target = utils::Config;
+
+    fn deref(&self) -> &utils::Config {
+        #[cfg(test)]
+        {
+            if let Some(config) = tests::config_override() {
+                return config;
+            }
+        }
+        &FILE_CONFIG
+    }
+}
+
+pub(crate) fn load_config() -> utils::Config {
+    serde_json::from_str(std::include_str!("../config.json")).unwrap()
+}
+
 fn build_routes() -> router::Router {
     let mut router = router::Router::new(&default_route);
     router.add_route(blog::IMG_CACHE_PREFIX, &proxy_remote_image);
     sn::build_routes(&mut router);
     render::build_routes(&mut router);
+    backup::build_routes(&mut router);
+    media::build_routes(&mut router);
+    revisions::build_routes(&mut router);
+    admin::build_routes(&mut router);
     return router;
 }
 
@@ -60,7 +97,7 @@ extern "C" {
     // In cloudflare workers, there's no Window object
     // and unfortunately the bionding in web_sys depends
     // on Window being present.
-    fn fetch(req: &Request) -> Promise;
+    pub(crate) fn fetch(req: &Request) -> Promise;
 }
 
 macro_rules! get_header {
@@ -149,41 +186,67 @@ async fn default_route(_req: Request, url: Url) -> MyResult<Response> {
 
     // Now we can be sure the path ends with `/`
     // (and of course it starts with `/` as per standard)
-    if path.len() > 1 {
-        let path = &path[1..path.len() - 1];
-        if let Ok(post) = blog::Post::find_by_url(path).await {
-            if post.url != path {
-                // Redirect to the latest path of the post
-                return Response::new_with_opt_str_and_init(
-                    None,
-                    ResponseInit::new()
-                        .status(301)
-                        .headers(headers!{
-                            "Location" => &format!("{}/{}/", url.origin(), post.url)
-                        }.as_ref())
-                ).internal_err();
-            } else {
-                // Render the page
-                return Response::new_with_opt_str_and_init(
-                    Some(&render::render_post(url, post).await?),
-                    ResponseInit::new()
-                        .status(200)
-                        .headers(headers!{
-                            "Content-Type" => "text/html",
-                            "Cache-Control" => "no-cache"
-                        }.as_ref())
-                ).internal_err();
-            }
-        }
+    let path = &path[1..path.len() - 1];
+    let post = match blog::Post::find_by_url(path).await {
+        Ok(post) => post,
+        // Deleted posts answer 410 instead of 404
+        Err(e @ Error::Gone(_)) => return Err(e),
+        Err(_) => return Err(Error::NotFound("This page is not available".into()))
+    };
+    if post.is_pending() {
+        return Err(Error::NotFound("This page is not available".into()));
     }
 
-    Err(Error::NotFound("This page is not available".into()))
+    if post.url != path {
+        // Redirect to the latest path of the post
+        Response::new_with_opt_str_and_init(
+            None,
+            ResponseInit::new()
+                .status(301)
+                .headers(headers!{
+                    "Location" => &format!("{}/{}/", url.origin(), post.url)
+                }.as_ref())
+        ).internal_err()
+    } else {
+        // Render the page
+        let html = match post.kind {
+            blog::PostKind::Post => render::render_post(url, post).await?,
+            blog::PostKind::Page => render::render_page(url, post).await?
+        };
+        Response::new_with_opt_str_and_init(
+            Some(&html),
+            ResponseInit::new()
+                .status(200)
+                .headers(headers!{
+                    "Content-Type" => "text/html",
+                    "Cache-Control" => "no-cache"
+                }.as_ref())
+        ).internal_err()
+    }
 }
 
 task_local! {
     pub static EVENT: ExtendableEvent;
 }
 
+// Entry point for Cron Triggers
+// `scheduled_time` (in milliseconds, `scheduledTime` of the event)
+// is used as the current time, so tests can run it at any time they want
+#[wasm_bindgen]
+pub async fn handle_scheduled_rs(ev: ExtendableEvent, scheduled_time: f64) -> Result<u32, JsValue> {
+    let result = EVENT.scope(ev, store::with_read_cache(async move {
+        schedule::publish_due_posts(scheduled_time as u64 / 1000).await
+    })).await;
+
+    match result {
+        Ok(published) => Ok(published as u32),
+        Err(err) => {
+            let reason: String = err.into();
+            Err(reason.into())
+        }
+    }
+}
+
 #[wasm_bindgen]
 pub async fn handle_request_rs(ev: ExtendableEvent, req: Request) -> Response {
     let url = Url::new(&req.url()).unwrap();
@@ -196,9 +259,9 @@ pub async fn handle_request_rs(ev: ExtendableEvent, req: Request) -> Response {
         ).unwrap();
     }
 
-    let result = EVENT.scope(ev, async move {
+    let result = EVENT.scope(ev, store::with_read_cache(async move {
         ROUTER.execute(req, url).await
-    }).await;
+    })).await;
 
     match result {
         Ok(resp) => resp,
@@ -0,0 +1,94 @@
+// Media files (images, attachments, etc.) hosted by Paprika itself
+// Files are uploaded with `PUT /media/<name>?secret=...` and served
+// publicly at `/media/<name>`. Unlike `/imgcache/`, nothing depends
+// on a remote server staying up.
+use crate::store;
+use crate::router::Router;
+use crate::utils::*;
+use web_sys::*;
+
+pub const MEDIA_PREFIX: &'static str = "/media/";
+pub const MEDIA_KEY_PREFIX: &'static str = "media_";
+// Files can be replaced or deleted at the same URL, so unlike static
+// files (whose URLs change with every build) they are only cached briefly
+const CACHE_CONTROL_MEDIA: &'static str = "public, max-age=300";
+
+pub fn build_routes(router: &mut Router) {
+    router.add_route(MEDIA_PREFIX, &handle_media);
+}
+
+// Extract the file name from `/media/<name>`
+fn media_name(url: &Url) -> MyResult<String> {
+    let path = url.pathname();
+    let name: String = js_sys::decode_uri_component(&path[MEDIA_PREFIX.len()..])
+        .map_err(|_| Error::BadRequest("Malformed file name".into()))?.into();
+    if name.is_empty() {
+        return Err(Error::NotFound("This file does not exist".into()));
+    }
+    Ok(name)
+}
+
+async fn handle_media(req: Request, url: Url) -> MyResult<Response> {
+    match req.method().as_str() {
+        "GET" => serve_media(url).await,
+        "PUT" => upload_media(req, url).await,
+        "DELETE" => delete_media(url).await,
+        _ => Err(Error::BadRequest("Unsupported method".into()))
+    }
+}
+
+async fn serve_media(url: Url) -> MyResult<Response> {
+    let name = media_name(&url)?;
+    let value = store::get_stream(&format!("{}{}", MEDIA_KEY_PREFIX, name)).await?
+        .ok_or(Error::NotFound("This file does not exist".into()))?;
+    let content_type = value.content_type.unwrap_or_else(|| {
+        mime_guess::from_path(&name).first_or_octet_stream().essence_str().to_owned()
+    });
+
+    Response::new_with_opt_readable_stream_and_init(
+        Some(&value.stream),
+        ResponseInit::new()
+            .status(200)
+            .headers(headers!{
+                "Content-Type" => &content_type,
+                "Cache-Control" => CACHE_CONTROL_MEDIA
+            }.as_ref())
+    ).internal_err()
+}
+
+// The content type is taken from the `Content-Type` header of the request,
+// or guessed from the file name if absent
+// Existing files with the same name are replaced
+async fn upload_media(req: Request, url: Url) -> MyResult<Response> {
+    verify_secret!(url, params);
+    let name = media_name(&url)?;
+    let body = req.body().ok_or(Error::BadRequest("Empty file".into()))?;
+    let content_type = match req.headers().get("content-type").internal_err()? {
+        Some(content_type) => content_type,
+        None => mime_guess::from_path(&name).first_or_octet_stream().essence_str().to_owned()
+    };
+    store::put_stream(&format!("{}{}", MEDIA_KEY_PREFIX, name), &body, Some(&content_type)).await?;
+
+    Response::new_with_opt_str_and_init(
+        Some(&serde_json::json!({ "url": url.pathname() }).to_string()),
+        ResponseInit::new()
+            .status(200)
+            .headers(headers!{
+                "Content-Type" => "application/json",
+                "Cache-Control" => "no-cache"
+            }.add_cors().as_ref())
+    ).internal_err()
+}
+
+async fn delete_media(url: Url) -> MyResult<Response> {
+    verify_secret!(url, params);
+    let name = media_name(&url)?;
+    store::delete(&format!("{}{}", MEDIA_KEY_PREFIX, name)).await?;
+
+    Response::new_with_opt_str_and_init(
+        None,
+        ResponseInit::new()
+            .status(200)
+            .headers(headers!().add_cors().as_ref())
+    ).internal_err()
+}
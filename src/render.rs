@@ -17,8 +17,13 @@ include!(concat!(env!("OUT_DIR"), "/load_theme.rs"));
 pub fn build_routes(router: &mut Router) {
     router.add_route("/static/", &serve_static);
     router.add_route("/feed.xml", &serve_rss);
+    router.add_route(TAG_PREFIX, &serve_tag);
+    router.add_route(AUTHOR_PREFIX, &serve_author);
 }
 
+pub const TAG_PREFIX: &'static str = "/tag/";
+pub const AUTHOR_PREFIX: &'static str = "/author/";
+
 async fn serve_static(_req: Request, url: Url) -> MyResult<Response> {
     let path = url.pathname();
 
@@ -40,7 +45,7 @@ async fn serve_static(_req: Request, url: Url) -> MyResult<Response> {
 
 async fn serve_rss(_req: Request, url: Url) -> MyResult<Response> {
     Response::new_with_opt_str_and_init(
-        Some(&_render_homepage(url, "rss.hbs").await?),
+        Some(&_render_homepage(url, "rss.hbs", false).await?),
         ResponseInit::new()
             .status(200)
             .headers(headers!{
@@ -49,6 +54,62 @@ async fn serve_rss(_req: Request, url: Url) -> MyResult<Response> {
     ).internal_err()
 }
 
+// `/tag/<name>/`: published posts with the tag `name`
+async fn serve_tag(_req: Request, url: Url) -> MyResult<Response> {
+    let path = url.pathname();
+    // Normalize the path first, just like posts
+    if !path.ends_with("/") {
+        return Response::new_with_opt_str_and_init(
+            None,
+            ResponseInit::new()
+                .status(302)
+                .headers(headers!{
+                    "Location" => &format!("{}{}/", url.origin(), path)
+                }.as_ref())
+        ).internal_err();
+    }
+
+    let tag: String = js_sys::decode_uri_component(&path[TAG_PREFIX.len()..path.len() - 1])
+        .map_err(|_| Error::BadRequest("Malformed tag".into()))?.into();
+    Response::new_with_opt_str_and_init(
+        Some(&render_tag_page(url, tag).await?),
+        ResponseInit::new()
+            .status(200)
+            .headers(headers!{
+                "Content-Type" => "text/html",
+                "Cache-Control" => "no-cache"
+            }.as_ref())
+    ).internal_err()
+}
+
+// `/author/<id>/`: published posts by the author `id`
+async fn serve_author(_req: Request, url: Url) -> MyResult<Response> {
+    let path = url.pathname();
+    // Normalize the path first, just like posts
+    if !path.ends_with("/") {
+        return Response::new_with_opt_str_and_init(
+            None,
+            ResponseInit::new()
+                .status(302)
+                .headers(headers!{
+                    "Location" => &format!("{}{}/", url.origin(), path)
+                }.as_ref())
+        ).internal_err();
+    }
+
+    let id: String = js_sys::decode_uri_component(&path[AUTHOR_PREFIX.len()..path.len() - 1])
+        .map_err(|_| Error::BadRequest("Malformed author".into()))?.into();
+    Response::new_with_opt_str_and_init(
+        Some(&render_author_page(url, id).await?),
+        ResponseInit::new()
+            .status(200)
+            .headers(headers!{
+                "Content-Type" => "text/html",
+                "Cache-Control" => "no-cache"
+            }.as_ref())
+    ).internal_err()
+}
+
 // Context objects used when rendering pages
 #[derive(Serialize)]
 struct BlogRootContext {
@@ -71,7 +132,35 @@ struct PageContext {
     // Summary of the current page
     // regardless of its type
     // For use in <meta> tags
-    description: String
+    description: String,
+    // Published standalone pages, e.g. for navigation
+    pages: Vec<PageLink>
+}
+
+#[derive(Serialize)]
+struct PageLink {
+    title: String,
+    url: String
+}
+
+#[derive(Serialize)]
+struct PostTag {
+    name: String,
+    // Path to the tag page, e.g. `/tag/rust/`
+    url: String
+}
+
+// An entry of `authors` in config
+#[derive(Serialize)]
+struct AuthorContext {
+    id: String,
+    name: &'static str,
+    bio: Option<&'static str>,
+    avatar: Option<&'static str>,
+    url: Option<&'static str>,
+    email: Option<&'static str>,
+    // Path to the author's page, e.g. `/author/someone/`
+    page_url: String
 }
 
 #[derive(Serialize)]
@@ -79,7 +168,13 @@ struct HomePagePost {
     title: String,
     url: String,
     timestamp: u64,
-    summary: String
+    summary: String,
+    tags: Vec<PostTag>,
+    pinned: bool,
+    author: Option<AuthorContext>,
+    word_count: usize,
+    // In minutes
+    reading_time: u64
 }
 
 #[derive(Serialize)]
@@ -88,7 +183,46 @@ struct HomePageContext {
     page: PageContext,
     posts: Vec<HomePagePost>,
     prev: Option<String>,
-    next: Option<String>
+    next: Option<String>,
+    // The tag being browsed on tag pages; None on the home page
+    tag: Option<String>,
+    // The author being browsed on author pages
+    author: Option<AuthorContext>
+}
+
+// Standalone pages (`kind: "page"`)
+#[derive(Serialize)]
+struct StandalonePageContext {
+    blog: &'static BlogRootContext,
+    page: PageContext,
+    title: String,
+    url: String,
+    content: String,
+    theme_config: Option<serde_json::Value>,
+    toc: Vec<blog::TocEntry>
+}
+
+#[derive(Serialize)]
+struct NeighbourPost {
+    title: String,
+    url: String
+}
+
+#[derive(Serialize)]
+struct SeriesPost {
+    title: String,
+    url: String,
+    // Whether this is the post being rendered
+    current: bool
+}
+
+#[derive(Serialize)]
+struct SeriesContext {
+    title: String,
+    posts: Vec<SeriesPost>,
+    // 1-based position of the current post
+    position: usize,
+    total: usize
 }
 
 #[derive(Serialize)]
@@ -99,7 +233,20 @@ struct PostContext {
     url: String,
     timestamp: u64,
     content: String,
-    theme_config: Option<serde_json::Value>
+    theme_config: Option<serde_json::Value>,
+    tags: Vec<PostTag>,
+    author: Option<AuthorContext>,
+    word_count: usize,
+    // In minutes
+    reading_time: u64,
+    // Table of contents, nested by heading level
+    toc: Vec<blog::TocEntry>,
+    // None if the post is not part of a (published) series
+    series: Option<SeriesContext>,
+    // The older and newer posts next to this one on the home page
+    // Both are None for unlisted posts
+    prev_post: Option<NeighbourPost>,
+    next_post: Option<NeighbourPost>
 }
 
 lazy_static! {
@@ -119,6 +266,21 @@ lazy_static! {
 
 handlebars_helper!(cur_year: | | Date::new_0().get_full_year());
 handlebars_helper!(build_num: | | BUILD_TIMESTAMP);
+// The KaTeX stylesheet, if math is typeset: the copy shipped with the theme,
+// or the one matching the bundled KaTeX on jsDelivr if `katex_cdn` allows it
+// Empty if there is neither, or if KaTeX is not built in
+#[cfg(katex)]
+const KATEX_CSS_PATH: &'static str = "static/katex/katex.min.css";
+#[cfg(katex)]
+handlebars_helper!(katex_css: | | if THEME_DIR.get_file(KATEX_CSS_PATH).is_some() {
+    format!("/{}", KATEX_CSS_PATH)
+} else if crate::CONFIG.katex_cdn {
+    format!("https://cdn.jsdelivr.net/npm/katex@{}/dist/katex.min.css", crate::katex::KATEX_VERSION)
+} else {
+    String::new()
+});
+#[cfg(not(katex))]
+handlebars_helper!(katex_css: | | "");
 handlebars_helper!(format_date: |date: u64, format: str| {
     NaiveDateTime::from_timestamp(date as i64, 0).format(format).to_string()
 });
@@ -132,6 +294,7 @@ fn build_handlebars() -> Handlebars<'static> {
     // Helpers
     hbs.register_helper("cur_year", Box::new(cur_year));
     hbs.register_helper("build_num", Box::new(build_num));
+    hbs.register_helper("katex_css", Box::new(katex_css));
     hbs.register_helper("format_date", Box::new(format_date));
     hbs.register_helper("feed_pagination", Box::new(feed_pagination));
 
@@ -152,36 +315,126 @@ fn build_handlebars() -> Handlebars<'static> {
     return hbs;
 }
 
-fn build_page_context(url: &Url, description: String) -> PageContext {
-    PageContext {
+fn tag_url(tag: &str) -> String {
+    let encoded: String = js_sys::encode_uri_component(tag).into();
+    format!("{}{}/", TAG_PREFIX, encoded)
+}
+
+fn build_post_tags(tags: &[String]) -> Vec<PostTag> {
+    tags.iter().map(|tag| PostTag {
+        name: tag.clone(),
+        url: tag_url(tag)
+    }).collect()
+}
+
+fn author_url(id: &str) -> String {
+    let encoded: String = js_sys::encode_uri_component(id).into();
+    format!("{}{}/", AUTHOR_PREFIX, encoded)
+}
+
+// None for authors that have been removed from config
+fn build_author_context(id: Option<&str>) -> Option<AuthorContext> {
+    let id = id?;
+    let author = crate::CONFIG.authors.get(id)?;
+    Some(AuthorContext {
+        id: id.into(),
+        name: &author.name,
+        bio: author.bio.as_deref(),
+        avatar: author.avatar.as_deref(),
+        url: author.url.as_deref(),
+        email: author.email.as_deref(),
+        page_url: author_url(id)
+    })
+}
+
+async fn build_page_context(url: &Url, description: String) -> MyResult<PageContext> {
+    let pages = blog::PagesList::load().await?.pages.into_iter()
+        .map(|p| PageLink { title: p.title, url: p.url })
+        .collect();
+    Ok(PageContext {
         base_url: url.origin(),
         pathname: url.pathname(),
         query: url.search(),
-        description
-    }
+        description,
+        pages
+    })
 }
 
 pub fn render_homepage(url: Url) -> impl std::future::Future<Output = MyResult<String>> {
-    _render_homepage(url, "home.hbs")
+    _render_homepage(url, "home.hbs", true)
 }
 
 // Shared logic for both homepage rendering and RSS
 // RSS is just the "homepage" (i.e. post list) rendered
 // to XML RSS format, which is done by a common template
-async fn _render_homepage(url: Url, tpl_name: &str) -> MyResult<String> {
+// With `pin`, pinned posts are moved to the top of the first page
+// (RSS readers sort by date anyway, so the feed keeps the usual order)
+async fn _render_homepage(url: Url, tpl_name: &str, pin: bool) -> MyResult<String> {
+    let mut posts = blog::PostsList::load().await?.posts;
+    if pin {
+        let pinned = blog::PostsList::load_pinned().await?.posts;
+        // Pinned posts are taken out of the list (so they don't appear twice)
+        // and keep their relative order; only published posts can be pinned
+        let (mut top, rest): (Vec<String>, Vec<String>) = posts.into_iter()
+            .partition(|uuid| pinned.contains(uuid));
+        top.extend(rest);
+        posts = top;
+    }
+    render_post_list(url, tpl_name, &posts, "/", None, None).await
+}
+
+// Tag pages have the same context as the home page, with `tag` set
+// Themes without `tag.hbs` get the home page template instead
+pub async fn render_tag_page(url: Url, tag: String) -> MyResult<String> {
+    let tagged = blog::PostsList::load_tag(&tag).await?.posts;
+    let posts: Vec<String> = blog::PostsList::load().await?.posts.into_iter()
+        .filter(|uuid| tagged.contains(uuid))
+        .collect();
+    if posts.is_empty() {
+        return Err(Error::NotFound("No post has this tag".into()));
+    }
+
+    let tpl_name = if HANDLEBARS.has_template("tag.hbs") { "tag.hbs" } else { "home.hbs" };
+    render_post_list(url, tpl_name, &posts, &tag_url(&tag), Some(tag), None).await
+}
+
+// Author pages have the same context as the home page, with `author` set
+// Themes without `author.hbs` get the home page template instead
+pub async fn render_author_page(url: Url, id: String) -> MyResult<String> {
+    let author = build_author_context(Some(&id))
+        .ok_or(Error::NotFound("This author does not exist".into()))?;
+    let by_author = blog::PostsList::load_author(&id).await?.posts;
+    let posts: Vec<String> = blog::PostsList::load().await?.posts.into_iter()
+        .filter(|uuid| by_author.contains(uuid))
+        .collect();
+    if posts.is_empty() {
+        return Err(Error::NotFound("This author has no post".into()));
+    }
+
+    let tpl_name = if HANDLEBARS.has_template("author.hbs") { "author.hbs" } else { "home.hbs" };
+    render_post_list(url, tpl_name, &posts, &author_url(&id), None, Some(author)).await
+}
+
+// Render one page of `posts` (UUIDs) starting from the `offset` in the query string
+// `base_path` is where the list lives, used for links to other pages
+async fn render_post_list(
+    url: Url, tpl_name: &str, posts: &[String], base_path: &str,
+    tag: Option<String>, author: Option<AuthorContext>
+) -> MyResult<String> {
     let params = UrlSearchParams::new_with_str(&url.search())
         .map_err(|_| Error::BadRequest("Failed to parse query string".into()))?;
     let mut context = HomePageContext {
         blog: &ROOT_CONTEXT,
-        page: build_page_context(&url, ROOT_CONTEXT.description.into()),
+        page: build_page_context(&url, ROOT_CONTEXT.description.into()).await?,
         posts: vec![],
         prev: None,
-        next: None
+        next: None,
+        tag,
+        author
     };
-    let posts_list = blog::PostsList::load().await;
 
     // Pagination
-    let mut posts_len = posts_list.0.len();
+    let mut posts_len = posts.len();
     let mut offset: isize = 0;
     if let Some(offset_str) = params.get("offset") {
         offset = offset_str.parse().internal_err()?;
@@ -195,9 +448,9 @@ async fn _render_homepage(url: Url, tpl_name: &str) -> MyResult<String> {
         let new_offset =
             std::cmp::max(offset - crate::CONFIG.posts_per_page as isize, 0) as usize;
         if new_offset != 0 {
-            context.prev = Some(format!("/?offset={}", new_offset));
+            context.prev = Some(format!("{}?offset={}", base_path, new_offset));
         } else {
-            context.prev = Some("/".into());
+            context.prev = Some(base_path.into());
         }
     }
 
@@ -207,37 +460,104 @@ async fn _render_homepage(url: Url, tpl_name: &str) -> MyResult<String> {
 
     if posts_len > crate::CONFIG.posts_per_page {
         context.next = Some(
-            format!("/?offset={}",
+            format!("{}?offset={}", base_path,
                 offset + crate::CONFIG.posts_per_page as isize));
     }
     
     // List posts
-    for uuid in posts_list.0.iter().skip(offset as usize).take(crate::CONFIG.posts_per_page) {
+    for uuid in posts.iter().skip(offset as usize).take(crate::CONFIG.posts_per_page) {
         let post = blog::Post::find_by_uuid(uuid).await?;
         let post_cache = blog::PostContentCache::find_or_render(&post).await;
         context.posts.push(HomePagePost {
             title: post.title,
             url: post.url,
             timestamp: post.timestamp,
-            summary: post_cache.summary
+            summary: post_cache.summary,
+            tags: build_post_tags(&post.tags),
+            pinned: post.pinned,
+            author: build_author_context(post.author.as_deref()),
+            word_count: post_cache.word_count,
+            reading_time: post_cache.reading_time
         });
     }
     HANDLEBARS.render(tpl_name, &context)
         .map_err(|e| Error::BadRequest(format!("{:#?}", e)))
 }
 
+async fn build_series_context(post: &blog::Post) -> MyResult<Option<SeriesContext>> {
+    let title = match post.series {
+        Some(ref title) => title,
+        None => return Ok(None)
+    };
+    let series = blog::Series::load(title).await?;
+    // Unlisted posts are not in the index
+    let position = match series.posts.iter().position(|p| p.uuid == post.uuid) {
+        Some(pos) => pos + 1,
+        None => return Ok(None)
+    };
+
+    Ok(Some(SeriesContext {
+        title: series.title,
+        total: series.posts.len(),
+        position,
+        posts: series.posts.into_iter().map(|p| SeriesPost {
+            current: p.uuid == post.uuid,
+            title: p.title,
+            url: p.url
+        }).collect()
+    }))
+}
+
+fn build_neighbour_post(link: Option<blog::NeighbourLink>) -> Option<NeighbourPost> {
+    link.map(|link| NeighbourPost {
+        title: link.title,
+        url: link.url
+    })
+}
+
 pub async fn render_post(url: Url, post: blog::Post) -> MyResult<String> {
     let post_cache = blog::PostContentCache::find_or_render(&post).await;
+    let series = build_series_context(&post).await?;
+    let neighbours = blog::PostNeighbours::load(&post.uuid).await?;
     let context = PostContext {
         blog: &ROOT_CONTEXT,
-        page: build_page_context(&url, strip_html_tags(&post_cache.summary)),
+        page: build_page_context(&url, strip_html_tags(&post_cache.summary)).await?,
         title: post.title,
         url: post.url,
         timestamp: post.timestamp,
         content: post_cache.content,
-        theme_config: post.theme_config
+        theme_config: post.theme_config,
+        tags: build_post_tags(&post.tags),
+        author: build_author_context(post.author.as_deref()),
+        word_count: post_cache.word_count,
+        reading_time: post_cache.reading_time,
+        toc: post_cache.toc,
+        series,
+        prev_post: build_neighbour_post(neighbours.prev),
+        next_post: build_neighbour_post(neighbours.next)
     };
 
     HANDLEBARS.render("post.hbs", &context)
         .map_err(|e| Error::BadRequest(format!("{:#?}", e)))
-}
\ No newline at end of file
+}
+
+// Themes without `page.hbs` get the post template instead
+pub async fn render_page(url: Url, post: blog::Post) -> MyResult<String> {
+    if !HANDLEBARS.has_template("page.hbs") {
+        return render_post(url, post).await;
+    }
+
+    let post_cache = blog::PostContentCache::find_or_render(&post).await;
+    let context = StandalonePageContext {
+        blog: &ROOT_CONTEXT,
+        page: build_page_context(&url, strip_html_tags(&post_cache.summary)).await?,
+        title: post.title,
+        url: post.url,
+        content: post_cache.content,
+        theme_config: post.theme_config,
+        toc: post_cache.toc
+    };
+
+    HANDLEBARS.render("page.hbs", &context)
+        .map_err(|e| Error::BadRequest(format!("{:#?}", e)))
+}
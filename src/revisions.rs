@@ -0,0 +1,172 @@
+// Listing and rolling back to previous revisions of posts
+// Revisions themselves are saved by `blog::Post::write_to_kv`
+use crate::{blog, store};
+use crate::router::Router;
+use crate::utils::*;
+use serde::Serialize;
+use std::vec::Vec;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::*;
+
+pub fn build_routes(router: &mut Router) {
+    router.add_route("/revisions", &list_revisions);
+    router.add_route("/rollback", &rollback_post);
+}
+
+#[derive(Serialize)]
+struct RevisionInfo {
+    revision: u64,
+    saved_at: u64,
+    title: String,
+    url: String,
+    timestamp: u64
+}
+
+#[derive(Serialize)]
+struct RevisionsList {
+    uuid: String,
+    // Version of the post as it is now
+    version: u64,
+    // Newest first
+    revisions: Vec<RevisionInfo>
+}
+
+#[derive(Serialize)]
+struct RollbackResult {
+    uuid: String,
+    // The revision rolled back to
+    revision: u64,
+    // New version of the post; the replaced version
+    // becomes a revision itself, so rollbacks can be undone
+    version: u64,
+    url: String
+}
+
+fn json_response<T: Serialize>(value: &T) -> MyResult<Response> {
+    Response::new_with_opt_str_and_init(
+        Some(&serde_json::to_string(value).internal_err()?),
+        ResponseInit::new()
+            .status(200)
+            .headers(headers!{
+                "Content-Type" => "application/json",
+                "Cache-Control" => "no-cache"
+            }.add_cors().as_ref())
+    ).internal_err()
+}
+
+// GET /revisions?uuid=<uuid>
+async fn list_revisions(req: Request, url: Url) -> MyResult<Response> {
+    verify_secret!(url, params);
+    if req.method() != "GET" {
+        return Err(Error::BadRequest("Unsupported method".into()));
+    }
+
+    let uuid = params.get("uuid").ok_or(Error::BadRequest("`uuid` needed".into()))?;
+    let post = blog::Post::find_by_uuid(&uuid).await
+        .map_err(|_| Error::NotFound("This post does not exist".into()))?;
+    let mut revisions = vec![];
+    for revision in blog::Post::list_revisions(&uuid).await?.into_iter().rev() {
+        let saved = blog::Post::find_revision(&uuid, revision).await?;
+        revisions.push(RevisionInfo {
+            revision,
+            saved_at: saved.saved_at,
+            title: saved.post.title,
+            url: saved.post.url,
+            timestamp: saved.post.timestamp
+        });
+    }
+
+    json_response(&RevisionsList {
+        uuid,
+        version: post.version,
+        revisions
+    })
+}
+
+// POST /rollback?uuid=<uuid>&revision=<revision>
+// Without `revision`, roll back to the latest one (i.e. undo the last update)
+// Without `uuid`, the post is taken from the Standard Notes item in the body,
+// so this can be used as an action
+async fn rollback_post(req: Request, url: Url) -> MyResult<Response> {
+    verify_secret!(url, params);
+    if req.method() != "POST" {
+        return Err(Error::BadRequest("Unsupported method".into()));
+    }
+
+    let uuid = match params.get("uuid") {
+        Some(uuid) => uuid,
+        None => {
+            let body: serde_json::Value = serde_json::from_str(
+                &JsFuture::from(req.text().internal_err()?)
+                    .await.internal_err()?
+                    .as_string().ok_or(Error::BadRequest("Unable to parse POST body".into()))?
+            ).map_err(|_| Error::BadRequest("Unable to parse POST body".into()))?;
+            body["items"][0]["uuid"].as_str()
+                .ok_or(Error::BadRequest("`uuid` needed".into()))?
+                .to_owned()
+        }
+    };
+    let revision = match params.get("revision") {
+        Some(revision) => revision.parse()
+            .map_err(|_| Error::BadRequest("Invalid revision".into()))?,
+        None => *blog::Post::list_revisions(&uuid).await?.last()
+            .ok_or(Error::NotFound("This post has no revision".into()))?
+    };
+
+    let post = roll_back(&uuid, revision).await?;
+    json_response(&RollbackResult {
+        uuid,
+        revision,
+        version: post.version,
+        url: post.url
+    })
+}
+
+// Replace the post with one of its revisions, keeping the
+// home page, tag, author, pinned and page lists, URL mappings and content cache in sync
+async fn roll_back(uuid: &str, revision: u64) -> MyResult<blog::Post> {
+    for attempt in 1..=store::MAX_CAS_ATTEMPTS {
+        let current = blog::Post::find_by_uuid(uuid).await
+            .map_err(|_| Error::NotFound("This post does not exist".into()))?;
+        let mut post = blog::Post::find_revision(uuid, revision).await?.post;
+        post.version = current.version;
+        // Which lists the post belongs to depends on its kind
+        if post.kind != current.kind {
+            return Err(Error::BadRequest(
+                "Cannot roll back to a revision of a different kind (post / page)".into()));
+        }
+        // Another post may have taken the URL of the revision since
+        if let Some(owner) = blog::Post::find_url_owner(&post.url).await? {
+            if owner != uuid {
+                return Err(Error::Conflict(format!("`{}` is the URL of another post", post.url)));
+            }
+        }
+
+        let listed = blog::PostsList::load().await?.posts.iter().any(|p| p == uuid);
+        if listed {
+            // The timestamp may change the position with `sort_by_timestamp`
+            blog::PostsList::add_post(uuid, post.timestamp).await?;
+            blog::PostsList::update_tags(uuid, &current.tags, &post.tags).await?;
+            blog::PostsList::set_pinned(uuid, post.pinned).await?;
+            blog::PostsList::set_author(uuid, current.author.as_deref(), post.author.as_deref()).await?;
+        }
+        blog::PostContentCache::find_or_render(&post).await;
+        // The URL mapping of the revision is restored here, while the current URL
+        // stays mapped to the post, redirecting to the restored one
+        match post.write_to_kv().await {
+            Err(Error::Conflict(_)) if attempt < store::MAX_CAS_ATTEMPTS => continue,
+            res => {
+                res?;
+                if listed {
+                    blog::Series::sync_post(uuid, current.series.as_deref(), Some(&post)).await?;
+                    blog::PostNeighbours::relink(&post).await?;
+                }
+                if blog::PagesList::load().await?.pages.iter().any(|p| p.uuid == uuid) {
+                    blog::PagesList::sync_page(uuid, Some(&post)).await?;
+                }
+                return Ok(post);
+            }
+        }
+    }
+    Err(Error::Conflict(format!("Post {} has been updated concurrently, please try again", uuid)))
+}
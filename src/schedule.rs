@@ -0,0 +1,68 @@
+// Scheduled publishing
+// Posts with `publish_at` in the future are parked in the pending list
+// instead of `posts_list`. Every run of the Cron Trigger (see
+// `handle_scheduled_rs`) publishes those whose time has come.
+use crate::{blog, store};
+use crate::utils::*;
+
+// Publish every pending post with `publish_at <= now` (in seconds)
+// Returns how many posts were published
+pub async fn publish_due_posts(now: u64) -> MyResult<usize> {
+    let mut due = vec![];
+    for uuid in blog::PostsList::load_pending().await?.posts {
+        match blog::Post::find_by_uuid(&uuid).await {
+            Ok(post) => match post.publish_at {
+                Some(publish_at) if publish_at > now => (),
+                // None if the post was published but couldn't be listed last time
+                publish_at => due.push((publish_at.unwrap_or(0), uuid))
+            },
+            // Deleted in the meantime
+            Err(_) => blog::PostsList::remove_pending(&uuid).await?
+        }
+    }
+
+    // New posts go to the top of the list, so the one due last ends up on top
+    due.sort();
+    for (_, uuid) in due.iter() {
+        publish_post(uuid).await?;
+    }
+    Ok(due.len())
+}
+
+async fn publish_post(uuid: &str) -> MyResult<()> {
+    let mut tags = vec![];
+    let mut timestamp = 0;
+    let mut pinned = false;
+    let mut published = None;
+    for attempt in 1..=store::MAX_CAS_ATTEMPTS {
+        let mut post = blog::Post::find_by_uuid(uuid).await?;
+        // Pre-render now, so the first visitor doesn't have to wait
+        blog::PostContentCache::find_or_render(&post).await;
+        tags = post.tags.clone();
+        timestamp = post.timestamp;
+        pinned = post.pinned;
+        if post.publish_at.is_none() {
+            published = Some(post);
+            break;
+        }
+
+        post.publish_at = None;
+        match post.write_to_kv().await {
+            Err(Error::Conflict(_)) if attempt < store::MAX_CAS_ATTEMPTS => continue,
+            res => {
+                res?;
+                published = Some(post);
+                break;
+            }
+        }
+    }
+
+    blog::PostsList::add_post(uuid, timestamp).await?;
+    blog::PostsList::update_tags(uuid, &[], &tags).await?;
+    blog::PostsList::set_pinned(uuid, pinned).await?;
+    if let Some(post) = published {
+        blog::PostsList::set_author(uuid, None, post.author.as_deref()).await?;
+        blog::Series::sync_post(uuid, None, Some(&post)).await?;
+    }
+    blog::PostsList::remove_pending(uuid).await
+}
@@ -1,5 +1,5 @@
 // Interface for Standard Notes (Actions)
-use crate::{CONFIG, blog};
+use crate::{CONFIG, blog, store};
 use crate::router::Router;
 use crate::utils::*;
 use js_sys::Date;
@@ -14,17 +14,9 @@ pub fn build_routes(router: &mut Router) {
     router.add_route("/delete", &delete_post);
 }
 
-macro_rules! verify_secret {
-    ($url:expr, $params:ident) => {
-        let $params = UrlSearchParams::new_with_str(&$url.search())
-            .map_err(|_| Error::BadRequest("Failed to parse query string".into()))?;
-        if !$params.has("secret") {
-            return Err(Error::BadRequest("Secret needed".into()));
-        } else if $params.get("secret").unwrap() != crate::CONFIG.secret {
-            return Err(Error::Unauthorized("Secret mismatch".into()));
-        }
-    };
-}
+// First path segments taken by other routes, which would shadow
+// posts and pages with such URLs
+const RESERVED_URL_SEGMENTS: &[&str] = &["tag", "author", "media", "static", "imgcache", "feed.xml"];
 
 async fn get_actions(_req: Request, url: Url) -> MyResult<Response> {
     verify_secret!(url, params);
@@ -65,6 +57,29 @@ async fn get_actions(_req: Request, url: Url) -> MyResult<Response> {
             access_type: Some(AccessType::Decrypted)
         });
 
+        // Rolling back only changes the blog; the note itself is left alone
+        let uuid = &post.as_ref().unwrap().uuid;
+        if !blog::Post::list_revisions(uuid).await?.is_empty() {
+            actions.push(Action {
+                label: "Roll Back to Previous Revision".into(),
+                url: format!("{}/rollback?secret={}", origin, CONFIG.secret.clone()),
+                verb: Verb::Post,
+                context: Context::Item,
+                content_types: vec![ContentType::Note],
+                access_type: Some(AccessType::Decrypted)
+            });
+
+            actions.push(Action {
+                label: "Show Revisions".into(),
+                url: format!("{}/revisions?secret={}&uuid={}", origin, CONFIG.secret.clone(),
+                    String::from(js_sys::encode_uri_component(uuid))),
+                verb: Verb::Show,
+                context: Context::Item,
+                content_types: vec![ContentType::Note],
+                access_type: None
+            });
+        }
+
         actions.push(Action {
             label: "Open Post".into(),
             url: format!("{}/{}/", preferred_url, post.unwrap().url),
@@ -118,6 +133,21 @@ struct CustomMetadata {
     url: Option<String>,
     // Same as Post.theme_config
     theme_config: Option<serde_json::Value>,
+    // Like `theme_config`, tags are replaced on every update,
+    // so a post without this field has no tags
+    tags: Option<Vec<String>>,
+    // Publish the post at this time instead of right away
+    // Same format as `timestamp`, which it also sets unless present
+    publish_at: Option<String>,
+    // Also replaced on every update
+    pinned: Option<bool>,
+    // Same as Post.series and Post.series_order, also replaced on every update
+    series: Option<String>,
+    series_order: Option<i64>,
+    // "post" (the default) or "page"
+    kind: Option<blog::PostKind>,
+    // A key of `authors` in config; also replaced on every update
+    author: Option<String>,
     timestamp: Option<String> // Should be something `js_sys::Date::parse` could handle
 }
 
@@ -126,7 +156,15 @@ struct Metadata {
     url: String,
     has_custom_url: bool,
     timestamp: u64, // Seconds
-    has_custom_timestamp: bool
+    has_custom_timestamp: bool,
+    tags: Vec<String>,
+    // Only if it's in the future
+    publish_at: Option<u64>, // Seconds
+    pinned: bool,
+    series: Option<String>,
+    series_order: Option<i64>,
+    kind: blog::PostKind,
+    author: Option<String>
 }
 
 // You can customize metadata by adding something like
@@ -134,7 +172,14 @@ struct Metadata {
 // ```json
 // {
 //     "url": "xxx-xxx-xxx",
-//     "timestamp": "YYYY-mm-dd"
+//     "timestamp": "YYYY-mm-dd",
+//     "tags": ["tag1", "tag2"],
+//     "publish_at": "YYYY-mm-ddTHH:MM:SSZ",
+//     "pinned": true,
+//     "series": "Some series",
+//     "series_order": 1,
+//     "kind": "page",
+//     "author": "some-author"
 // }
 // ```
 // 
@@ -162,14 +207,21 @@ fn parse_custom_metadata_from_content(text: String) -> MyResult<(Option<CustomMe
 
 // Generate metadata from uuid and title
 // Fill in default value if custom value not present
-fn build_metadata(custom: Option<CustomMetadata>, uuid: &str, title: &str) -> Metadata {
+fn build_metadata(custom: Option<CustomMetadata>, uuid: &str, title: &str) -> MyResult<Metadata> {
     // Default values
     let mut ret = Metadata {
         unlist: false,
         url: title_to_url(&uuid, &title),
         has_custom_url: false,
         timestamp: Date::now() as u64 / 1000, // Seconds
-        has_custom_timestamp: false
+        has_custom_timestamp: false,
+        tags: vec![],
+        publish_at: None,
+        pinned: false,
+        series: None,
+        series_order: None,
+        kind: blog::PostKind::Post,
+        author: None
     };
 
     if let Some(custom) = custom {
@@ -188,9 +240,59 @@ fn build_metadata(custom: Option<CustomMetadata>, uuid: &str, title: &str) -> Me
             ret.timestamp = Date::parse(&date) as u64 / 1000; // Seconds
             ret.has_custom_timestamp = true;
         }
+
+        if let Some(date) = custom.publish_at {
+            // Rather than publishing right away what was meant to be scheduled
+            let publish_at = Date::parse(&date);
+            if publish_at.is_nan() {
+                return Err(Error::BadRequest("Invalid publish_at".into()));
+            }
+            let publish_at = publish_at as u64 / 1000; // Seconds
+            if !ret.has_custom_timestamp {
+                ret.timestamp = publish_at;
+                ret.has_custom_timestamp = true;
+            }
+            if publish_at > Date::now() as u64 / 1000 {
+                ret.publish_at = Some(publish_at);
+            }
+        }
+
+        if let Some(pinned) = custom.pinned {
+            ret.pinned = pinned;
+        }
+
+        ret.series = custom.series.map(|s| s.trim().to_owned()).filter(|s| !s.is_empty());
+        ret.series_order = custom.series_order;
+
+        if let Some(tags) = custom.tags {
+            for tag in tags {
+                let tag = tag.trim();
+                if !tag.is_empty() && !ret.tags.iter().any(|t| t == tag) {
+                    ret.tags.push(tag.to_owned());
+                }
+            }
+        }
+
+        ret.author = custom.author;
+
+        if let Some(kind) = custom.kind {
+            ret.kind = kind;
+        }
+        if ret.kind == blog::PostKind::Page {
+            if !ret.has_custom_url {
+                ret.url = title_to_slug(title);
+            }
+            // None of these make sense outside of the list of posts
+            ret.tags = vec![];
+            ret.publish_at = None;
+            ret.pinned = false;
+            ret.series = None;
+            ret.series_order = None;
+            ret.author = None;
+        }
     }
     
-    ret
+    Ok(ret)
 }
 
 macro_rules! load_post_body {
@@ -220,50 +322,141 @@ async fn create_or_update_post(req: Request, url: Url) -> MyResult<Response> {
     let title = data.items[0].content.title.clone();
     let (custom_metadata, text) = parse_custom_metadata_from_content(text)?;
     let theme_config = custom_metadata.as_ref().and_then(|it| it.theme_config.clone());
-    let metadata = build_metadata(custom_metadata, &uuid, &title);
-    let post = match blog::Post::find_by_uuid(&uuid).await {
-        Ok(mut post) => {
-            post.content = text;
-            post.title = title;
-            post.theme_config = theme_config;
-
-            // Update metadata if custom ones are present
-            if metadata.has_custom_url {
-                post.url = metadata.url;
-            }
+    let metadata = build_metadata(custom_metadata, &uuid, &title)?;
+    if let Some(author) = &metadata.author {
+        if !crate::CONFIG.authors.contains_key(author) {
+            return Err(Error::BadRequest(format!("Author `{}` is not in config", author)));
+        }
+    }
 
-            if metadata.has_custom_timestamp {
-                post.timestamp = metadata.timestamp;
-            }
+    // Update the lists first, so that nothing is written if they are broken
+    // Unlisted posts are not listed under their tags either
+    // Scheduled posts are listed by the scheduler when their time comes
+    // (unlisted ones just become accessible at that time)
+    let existing = blog::Post::find_by_uuid(&uuid).await.ok();
+    // Same rule as for the post itself below
+    let url = match &existing {
+        Some(post) if !metadata.has_custom_url => post.url.clone(),
+        _ => metadata.url.clone()
+    };
+    // e.g. pages titled only with non-ASCII characters
+    if url.trim_matches('/').is_empty() {
+        return Err(Error::BadRequest("Unable to generate a URL from the title, please set `url`".into()));
+    }
+    let first_segment = url.trim_matches('/').split('/').next().unwrap_or("");
+    if RESERVED_URL_SEGMENTS.contains(&first_segment) {
+        return Err(Error::BadRequest(format!("`{}` is reserved and cannot start a URL", first_segment)));
+    }
+    if let Some(owner) = blog::Post::find_url_owner(&url).await? {
+        if owner != uuid {
+            return Err(Error::Conflict(format!("`{}` is the URL of another post", url)));
+        }
+    }
+    let (old_tags, old_timestamp, old_series, old_author) = match existing {
+        Some(post) => (post.tags, Some(post.timestamp), post.series, post.author),
+        None => (vec![], None, None, None)
+    };
+    let is_page = metadata.kind == blog::PostKind::Page;
+    // Pages are never listed with posts
+    let listed = !is_page && !metadata.unlist && metadata.publish_at.is_none();
+    // Same rule as for the post itself below
+    let timestamp = match old_timestamp {
+        Some(timestamp) if !metadata.has_custom_timestamp => timestamp,
+        _ => metadata.timestamp
+    };
+    if listed {
+        blog::PostsList::add_post(&uuid, timestamp).await?;
+        blog::PostsList::update_tags(&uuid, &old_tags, &metadata.tags).await?;
+        blog::PostsList::set_pinned(&uuid, metadata.pinned).await?;
+        blog::PostsList::set_author(&uuid, old_author.as_deref(), metadata.author.as_deref()).await?;
+    } else {
+        blog::PostsList::remove_post(&uuid).await?;
+        blog::PostsList::update_tags(&uuid, &old_tags, &[]).await?;
+        blog::PostsList::set_pinned(&uuid, false).await?;
+        blog::PostsList::set_author(&uuid, old_author.as_deref(), None).await?;
+    }
+    if !metadata.unlist && metadata.publish_at.is_some() {
+        blog::PostsList::add_pending(&uuid).await?;
+    } else {
+        blog::PostsList::remove_pending(&uuid).await?;
+    }
 
-            post
-        },
-        Err(_) => {
-            blog::Post {
-                url: metadata.url,
-                uuid: uuid,
-                title: title,
-                content: text,
-                timestamp: metadata.timestamp,
-                theme_config: theme_config
+    // If the post gets updated by someone else (e.g. another device)
+    // between loading and saving it, load it again and re-apply
+    // the update on top of theirs
+    let mut saved = None;
+    for attempt in 1..=store::MAX_CAS_ATTEMPTS {
+        let mut post = match blog::Post::find_by_uuid(&uuid).await {
+            Ok(mut post) => {
+                post.content = text.clone();
+                post.title = title.clone();
+                post.theme_config = theme_config.clone();
+                post.tags = metadata.tags.clone();
+                post.publish_at = metadata.publish_at;
+                post.pinned = metadata.pinned;
+                post.series = metadata.series.clone();
+                post.series_order = metadata.series_order;
+                post.kind = metadata.kind;
+                post.author = metadata.author.clone();
+
+                // Update metadata if custom ones are present
+                if metadata.has_custom_url {
+                    post.url = metadata.url.clone();
+                }
+
+                if metadata.has_custom_timestamp {
+                    post.timestamp = metadata.timestamp;
+                }
+
+                post
+            },
+            Err(_) => {
+                blog::Post {
+                    url: metadata.url.clone(),
+                    uuid: uuid.clone(),
+                    title: title.clone(),
+                    content: text.clone(),
+                    timestamp: metadata.timestamp,
+                    theme_config: theme_config.clone(),
+                    tags: metadata.tags.clone(),
+                    publish_at: metadata.publish_at,
+                    pinned: metadata.pinned,
+                    kind: metadata.kind,
+                    author: metadata.author.clone(),
+                    url_history: vec![],
+                    series: metadata.series.clone(),
+                    series_order: metadata.series_order,
+                    version: 0
+                }
+            }
+        };
+
+        // Also pre-render the post
+        blog::PostContentCache::find_or_render(&post).await;
+        // Finally, save the post
+        match post.write_to_kv().await {
+            Err(Error::Conflict(_)) if attempt < store::MAX_CAS_ATTEMPTS => continue,
+            res => {
+                res?;
+                saved = Some(post);
+                break;
             }
         }
-    };
+    }
 
-    // Write the new post to storage
-    // As you may have seen by now, the process is far from atomic
-    // This is fine because we don't expect users to update posts from
-    // multiple endpoints simultaneously all the time
-    let list = blog::PostsList::load().await;
-    if !metadata.unlist {
-        list.add_post(&post.uuid).await?;
-    } else {
-        list.remove_post(&post.uuid).await?;
+    // The series index and neighbour pointers hold titles and URLs,
+    // so they can only be updated once the post itself has been saved
+    if let Some(post) = saved {
+        blog::Series::sync_post(&uuid, old_series.as_deref(),
+            if listed { Some(&post) } else { None }).await?;
+        blog::PagesList::sync_page(&uuid,
+            if is_page && !metadata.unlist { Some(&post) } else { None }).await?;
+        if listed {
+            blog::PostNeighbours::relink(&post).await?;
+        } else {
+            blog::PostNeighbours::clear(&uuid).await?;
+        }
     }
-    // Also pre-render the post
-    blog::PostContentCache::find_or_render(&post).await;
-    // Finally, save the post
-    post.write_to_kv().await?;
 
     Response::new_with_opt_str_and_init(
         None,
@@ -283,7 +476,15 @@ async fn delete_post(req: Request, url: Url) -> MyResult<Response> {
     load_post_body!(data, req);
 
     let uuid = &data.items[0].uuid;
-    blog::PostsList::load().await.remove_post(uuid).await?;
+    if let Ok(post) = blog::Post::find_by_uuid(uuid).await {
+        blog::PostsList::update_tags(uuid, &post.tags, &[]).await?;
+        blog::Series::sync_post(uuid, post.series.as_deref(), None).await?;
+        blog::PostsList::set_author(uuid, post.author.as_deref(), None).await?;
+    }
+    blog::PagesList::sync_page(uuid, None).await?;
+    blog::PostsList::set_pinned(uuid, false).await?;
+    blog::PostsList::remove_post(uuid).await?;
+    blog::PostsList::remove_pending(uuid).await?;
     blog::Post::delete_by_uuid(uuid).await?;
     blog::PostContentCache::delete_by_uuid(uuid).await?;
 
@@ -0,0 +1,148 @@
+// Bindings to Cloudflare Workers KV
+// This is the default storage backend, bound to the
+// KV namespace named `PAPRIKA` in `wrangler.toml`
+use crate::utils::*;
+use js_sys::{Array, ArrayBuffer, Object, Promise, Reflect, Uint8Array};
+use super::{BinaryValue, ListResult, Storage, StoreFuture, StreamValue};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::ReadableStream;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = PAPRIKA, js_name = "get")]
+    fn kv_get(key: &str) -> Promise;
+    #[wasm_bindgen(js_namespace = PAPRIKA, js_name = "getWithMetadata")]
+    fn kv_get_with_metadata(key: &str, value_type: &str) -> Promise;
+    #[wasm_bindgen(js_namespace = PAPRIKA, js_name = "put")]
+    fn kv_put_str(key: &str, value: &str) -> Promise;
+    #[wasm_bindgen(js_namespace = PAPRIKA, js_name = "put")]
+    fn kv_put_with_options(key: &str, value: &JsValue, options: &Object) -> Promise;
+    #[wasm_bindgen(js_namespace = PAPRIKA, js_name = "delete")]
+    fn kv_delete(key: &str) -> Promise;
+    #[wasm_bindgen(js_namespace = PAPRIKA, js_name = "list")]
+    fn kv_list(options: &Object) -> Promise;
+}
+
+pub struct KvStorage;
+
+// Content types are kept in the metadata of KV values, as `{ contentType: "..." }`
+async fn get_with_content_type(key: &str, value_type: &str) -> MyResult<Option<(JsValue, Option<String>)>> {
+    // The result looks like `{ value: ..., metadata: ... }`, with `value` being `null`
+    // for non-existent keys
+    let res = JsFuture::from(kv_get_with_metadata(key, value_type)).await.internal_err()?;
+    let value = Reflect::get(&res, &"value".into()).internal_err()?;
+    if value.is_null() || value.is_undefined() {
+        return Ok(None);
+    }
+    let metadata = Reflect::get(&res, &"metadata".into()).internal_err()?;
+    let content_type = if metadata.is_object() {
+        Reflect::get(&metadata, &"contentType".into()).internal_err()?.as_string()
+    } else {
+        None
+    };
+    Ok(Some((value, content_type)))
+}
+
+async fn put_with_content_type(key: &str, value: JsValue, content_type: Option<&str>) -> MyResult<()> {
+    let options = Object::new();
+    if let Some(content_type) = content_type {
+        let metadata = Object::new();
+        Reflect::set(&metadata, &"contentType".into(), &content_type.into()).internal_err()?;
+        Reflect::set(&options, &"metadata".into(), &metadata).internal_err()?;
+    }
+    JsFuture::from(kv_put_with_options(key, &value, &options)).await.internal_err()?;
+    Ok(())
+}
+
+impl Storage for KvStorage {
+    fn get_str<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            // KV returns `null` for non-existent keys
+            Ok(JsFuture::from(kv_get(key)).await.internal_err()?.as_string())
+        })
+    }
+
+    fn get_bytes<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<BinaryValue>> {
+        Box::pin(async move {
+            let (value, content_type) = match get_with_content_type(key, "arrayBuffer").await? {
+                Some(res) => res,
+                None => return Ok(None)
+            };
+            let buffer: ArrayBuffer = value.into();
+            Ok(Some(BinaryValue {
+                bytes: Uint8Array::new(&buffer).to_vec(),
+                content_type
+            }))
+        })
+    }
+
+    fn put_str<'a>(&'a self, key: &'a str, value: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            JsFuture::from(kv_put_str(key, value)).await.internal_err()?;
+            Ok(())
+        })
+    }
+
+    fn put_bytes<'a>(
+        &'a self, key: &'a str, value: &'a [u8], content_type: Option<&'a str>
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(put_with_content_type(key, Uint8Array::from(value).into(), content_type))
+    }
+
+    fn get_stream<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StreamValue>> {
+        Box::pin(async move {
+            let (value, content_type) = match get_with_content_type(key, "stream").await? {
+                Some(res) => res,
+                None => return Ok(None)
+            };
+            Ok(Some(StreamValue {
+                stream: value.into(),
+                content_type
+            }))
+        })
+    }
+
+    fn put_stream<'a>(
+        &'a self, key: &'a str, value: &'a ReadableStream, content_type: Option<&'a str>
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(put_with_content_type(key, value.into(), content_type))
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            JsFuture::from(kv_delete(key)).await.internal_err()?;
+            Ok(())
+        })
+    }
+
+    fn list<'a>(
+        &'a self, prefix: &'a str, cursor: Option<&'a str>, limit: usize
+    ) -> StoreFuture<'a, ListResult> {
+        Box::pin(async move {
+            let options = Object::new();
+            Reflect::set(&options, &"prefix".into(), &prefix.into()).internal_err()?;
+            Reflect::set(&options, &"limit".into(), &(limit as u32).into()).internal_err()?;
+            if let Some(cursor) = cursor {
+                Reflect::set(&options, &"cursor".into(), &cursor.into()).internal_err()?;
+            }
+
+            // The result looks like `{ keys: [{ name: "..." }], list_complete: bool, cursor: "..." }`
+            let res = JsFuture::from(kv_list(&options)).await.internal_err()?;
+            let keys: Array = Reflect::get(&res, &"keys".into()).internal_err()?.into();
+            let keys = keys.iter()
+                .filter_map(|k| Reflect::get(&k, &"name".into()).ok())
+                .filter_map(|name| name.as_string())
+                .collect();
+            let complete = Reflect::get(&res, &"list_complete".into()).internal_err()?
+                .as_bool().unwrap_or(true);
+            let cursor = if complete {
+                None
+            } else {
+                Reflect::get(&res, &"cursor".into()).internal_err()?.as_string()
+            };
+
+            Ok(ListResult { keys, cursor })
+        })
+    }
+}
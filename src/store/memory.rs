@@ -0,0 +1,109 @@
+// A storage backend that keeps everything in memory
+// Nothing is persisted across Worker instances, so this is only
+// useful for tests and for trying things out locally
+use crate::utils::*;
+use super::{BinaryValue, ListResult, Storage, StoreFuture};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::vec::Vec;
+
+struct Entry {
+    bytes: Vec<u8>,
+    content_type: Option<String>
+}
+
+pub struct MemoryStorage {
+    // BTreeMap so that listing is naturally sorted like KV
+    data: RefCell<BTreeMap<String, Entry>>
+}
+
+impl Default for MemoryStorage {
+    fn default() -> MemoryStorage {
+        MemoryStorage {
+            data: RefCell::new(BTreeMap::new())
+        }
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        Self::default()
+    }
+
+    // Returns all keys currently stored, for inspection in tests
+    pub fn keys(&self) -> Vec<String> {
+        self.data.borrow().keys().cloned().collect()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get_str<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            match self.data.borrow().get(key) {
+                Some(entry) => Ok(Some(String::from_utf8(entry.bytes.clone()).internal_err()?)),
+                None => Ok(None)
+            }
+        })
+    }
+
+    fn get_bytes<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<BinaryValue>> {
+        Box::pin(async move {
+            Ok(self.data.borrow().get(key).map(|entry| BinaryValue {
+                bytes: entry.bytes.clone(),
+                content_type: entry.content_type.clone()
+            }))
+        })
+    }
+
+    fn put_str<'a>(&'a self, key: &'a str, value: &'a str) -> StoreFuture<'a, ()> {
+        self.put_bytes(key, value.as_bytes(), None)
+    }
+
+    fn put_bytes<'a>(
+        &'a self, key: &'a str, value: &'a [u8], content_type: Option<&'a str>
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            self.data.borrow_mut().insert(key.into(), Entry {
+                bytes: value.to_vec(),
+                content_type: content_type.map(|t| t.to_owned())
+            });
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            self.data.borrow_mut().remove(key);
+            Ok(())
+        })
+    }
+
+    fn list<'a>(
+        &'a self, prefix: &'a str, cursor: Option<&'a str>, limit: usize
+    ) -> StoreFuture<'a, ListResult> {
+        Box::pin(async move {
+            // The cursor is simply the last key returned in the previous page
+            let start = match cursor {
+                Some(cursor) => Bound::Excluded(cursor.to_owned()),
+                None => Bound::Included(prefix.to_owned())
+            };
+            let data = self.data.borrow();
+            let mut keys: Vec<String> = data.range((start, Bound::Unbounded))
+                .map(|(k, _)| k)
+                .take_while(|k| k.starts_with(prefix))
+                .take(limit + 1)
+                .cloned()
+                .collect();
+
+            let cursor = if keys.len() > limit {
+                keys.truncate(limit);
+                keys.last().cloned()
+            } else {
+                None
+            };
+
+            Ok(ListResult { keys, cursor })
+        })
+    }
+}
@@ -0,0 +1,282 @@
+// Storage abstraction
+// Everything persisted by Paprika goes through the `Storage` trait.
+// The free functions in this module are what the rest of the program
+// uses, and they dispatch to whatever backend is currently installed
+// (Cloudflare Workers KV by default, see `kv.rs`)
+use crate::utils::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::vec::Vec;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{ReadableStream, Response};
+
+mod kv;
+mod memory;
+mod read_cache;
+mod s3;
+pub use kv::KvStorage;
+pub use memory::MemoryStorage;
+pub use read_cache::with_read_cache;
+pub use s3::{S3Config, S3Storage};
+
+// Futures returned by storage backends have to be boxed
+// because we can't have async functions in traits
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = MyResult<T>> + 'a>>;
+
+// One page of keys returned by `Storage::list`
+pub struct ListResult {
+    // Keys matching the prefix, in lexicographic order
+    pub keys: Vec<String>,
+    // Pass this to the next call to `list` to continue
+    // None if there are no more keys to list
+    pub cursor: Option<String>
+}
+
+// A binary value, along with the content type (MIME) it was stored with
+pub struct BinaryValue {
+    pub bytes: Vec<u8>,
+    pub content_type: Option<String>
+}
+
+// Same as BinaryValue, but the content is read lazily
+pub struct StreamValue {
+    pub stream: ReadableStream,
+    pub content_type: Option<String>
+}
+
+// A key-value storage backend
+// The semantics follow Cloudflare Workers KV, since that is
+// what Paprika was designed around: values are either strings
+// or raw bytes, and a missing key is not an error (Ok(None)).
+// Binary values may carry a content type, kept as metadata
+// alongside the value.
+pub trait Storage {
+    fn get_str<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<String>>;
+    fn get_bytes<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<BinaryValue>>;
+    fn put_str<'a>(&'a self, key: &'a str, value: &'a str) -> StoreFuture<'a, ()>;
+    fn put_bytes<'a>(
+        &'a self, key: &'a str, value: &'a [u8], content_type: Option<&'a str>
+    ) -> StoreFuture<'a, ()>;
+
+    // Backends without native streaming support buffer the whole value in memory
+    fn get_stream<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StreamValue>> {
+        Box::pin(async move {
+            let value = match self.get_bytes(key).await? {
+                Some(value) => value,
+                None => return Ok(None)
+            };
+            let mut bytes = value.bytes;
+            let stream = Response::new_with_opt_u8_array(Some(&mut bytes)).internal_err()?
+                .body().ok_or(Error::InternalError())?;
+            Ok(Some(StreamValue { stream, content_type: value.content_type }))
+        })
+    }
+
+    fn put_stream<'a>(
+        &'a self, key: &'a str, value: &'a ReadableStream, content_type: Option<&'a str>
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let buffer = Response::new_with_opt_readable_stream(Some(value)).internal_err()?
+                .array_buffer().internal_err()?;
+            let buffer: js_sys::ArrayBuffer = JsFuture::from(buffer).await.internal_err()?.into();
+            self.put_bytes(key, &js_sys::Uint8Array::new(&buffer).to_vec(), content_type).await
+        })
+    }
+
+    // Deleting a non-existent key is NOT an error
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()>;
+    // List at most `limit` keys starting with `prefix`, continuing
+    // from `cursor` (returned by a previous call) if present
+    fn list<'a>(
+        &'a self, prefix: &'a str, cursor: Option<&'a str>, limit: usize
+    ) -> StoreFuture<'a, ListResult>;
+}
+
+thread_local! {
+    // Workers are single-threaded, so a thread-local is
+    // effectively a global here
+    static BACKEND: RefCell<Rc<dyn Storage>> = RefCell::new(default_backend());
+}
+
+// The backend selected by `storage` in config.json
+fn default_backend() -> Rc<dyn Storage> {
+    match &crate::CONFIG.storage {
+        None | Some(StorageConfig::Kv) => Rc::new(KvStorage),
+        Some(StorageConfig::S3(config)) => Rc::new(S3Storage::new(config.clone()))
+    }
+}
+
+// Returns the storage backend currently in use
+pub fn backend() -> Rc<dyn Storage> {
+    BACKEND.with(|b| b.borrow().clone())
+}
+
+// Replace the storage backend used by everything in Paprika
+// This should be done before any request is handled
+pub fn set_backend(storage: Rc<dyn Storage>) {
+    BACKEND.with(|b| *b.borrow_mut() = storage);
+}
+
+// Returns empty string ("") if the key is not found
+pub async fn get_str(key: &str) -> MyResult<String> {
+    Ok(try_get_str(key).await?.unwrap_or("".into()))
+}
+
+// Returns None if the key is not found
+// Served from the read cache if the key has been read in the current request
+pub async fn try_get_str(key: &str) -> MyResult<Option<String>> {
+    if let Some(value) = read_cache::get(key) {
+        return Ok(value);
+    }
+    let backend = backend();
+    let value = backend.get_str(key).await?;
+    read_cache::remember(key, &value);
+    Ok(value)
+}
+
+pub async fn get_obj<T: DeserializeOwned>(key: &str) -> MyResult<T> {
+    let res = get_str(key).await?;
+    Ok(serde_json::from_str(&res).internal_err()?)
+}
+
+pub async fn put_str(key: &str, value: &str) -> MyResult<()> {
+    read_cache::invalidate(key);
+    let backend = backend();
+    backend.put_str(key, value).await
+}
+
+// Returns None if the key is not found
+pub async fn get_bytes(key: &str) -> MyResult<Option<BinaryValue>> {
+    let backend = backend();
+    backend.get_bytes(key).await
+}
+
+pub async fn put_bytes(key: &str, value: &[u8], content_type: Option<&str>) -> MyResult<()> {
+    read_cache::invalidate(key);
+    let backend = backend();
+    backend.put_bytes(key, value, content_type).await
+}
+
+// Returns None if the key is not found
+// Prefer this over `get_bytes` when the value is only passed on
+// (e.g. as a response body), so that it never has to be in memory as a whole
+pub async fn get_stream(key: &str) -> MyResult<Option<StreamValue>> {
+    let backend = backend();
+    backend.get_stream(key).await
+}
+
+pub async fn put_stream(key: &str, value: &ReadableStream, content_type: Option<&str>) -> MyResult<()> {
+    read_cache::invalidate(key);
+    let backend = backend();
+    backend.put_stream(key, value, content_type).await
+}
+
+pub async fn put_obj<T: Serialize>(key: &str, value: T) -> MyResult<()> {
+    put_str(key, &serde_json::to_string(&value).internal_err()?).await
+}
+
+// Some objects may be available for manual editing; thus making it pretty may be helpful
+// For example, the user may want to manually edit the order in which posts appear
+pub async fn put_obj_pretty<T: Serialize>(key: &str, value: T) -> MyResult<()> {
+    put_str(key, &serde_json::to_string_pretty(&value).internal_err()?).await
+}
+
+// Objects that carry a version number for optimistic concurrency control
+// The version should be stored in a top-level `version` field when serialized
+pub trait Versioned {
+    fn version(&self) -> u64;
+    fn set_version(&mut self, version: u64);
+}
+
+// How many times a load-modify-store cycle should be attempted
+// before giving up on a key that keeps being written concurrently
+pub const MAX_CAS_ATTEMPTS: usize = 5;
+
+// Write `value` to `key` only if the version stored there is still
+// `value.version()` (0 meaning the key does not exist yet), bumping
+// the version on success. Returns Ok(false) without writing anything
+// meaningful if someone else has written to `key` since `value` was loaded;
+// the caller should then reload, re-apply its changes and try again.
+// KV has no atomic compare-and-swap, so this is done on a best-effort
+// basis: the version is checked before writing, and the value is read
+// back afterwards to detect a concurrent write that landed after ours.
+// Both reads go to the backend directly, bypassing the read cache, and
+// the key is always dropped from the read cache so that the caller
+// reloads the latest value before trying again.
+pub async fn compare_and_put<T: Versioned + Serialize>(
+    key: &str, value: &mut T, pretty: bool
+) -> MyResult<bool> {
+    read_cache::invalidate(key);
+    let backend = backend();
+    let expected = value.version();
+    let current = match backend.get_str(key).await? {
+        // Records written before versioning was introduced count as version 0
+        Some(raw) => serde_json::from_str::<serde_json::Value>(&raw).ok()
+            .and_then(|v| v.get("version").and_then(|v| v.as_u64()))
+            .unwrap_or(0),
+        None => 0
+    };
+    if current != expected {
+        return Ok(false);
+    }
+
+    value.set_version(expected + 1);
+    let serialized = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }.internal_err()?;
+    put_str(key, &serialized).await?;
+
+    if backend.get_str(key).await?.as_ref() != Some(&serialized) {
+        value.set_version(expected);
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+pub async fn delete(key: &str) -> MyResult<()> {
+    read_cache::invalidate(key);
+    let backend = backend();
+    backend.delete(key).await
+}
+
+// Maximum number of keys returned in one page by `list`
+// (this is also the upper limit of Workers KV)
+pub const LIST_PAGE_SIZE: usize = 1000;
+
+// List one page of keys starting with `prefix`
+// Pass the `cursor` from the previous page to continue; the last page
+// has `cursor == None`. Keys are sorted lexicographically.
+pub async fn list(prefix: &str, cursor: Option<&str>) -> MyResult<ListResult> {
+    let backend = backend();
+    backend.list(prefix, cursor, LIST_PAGE_SIZE).await
+}
+
+// Walk through every page and return all keys starting with `prefix`
+// This needs one round-trip per LIST_PAGE_SIZE keys, so use `list`
+// directly if the caller can process keys page by page
+pub async fn list_all(prefix: &str) -> MyResult<Vec<String>> {
+    let mut ret = vec![];
+    let mut cursor: Option<String> = None;
+    loop {
+        let page = list(prefix, cursor.as_ref().map(|c| c.as_str())).await?;
+        ret.extend(page.keys);
+        match page.cursor {
+            Some(c) => cursor = Some(c),
+            None => return Ok(ret)
+        }
+    }
+}
+
+// Same as `list_all`, but with `prefix` stripped from every key
+// Useful for getting the IDs out of keys like `post_by_uuid_<uuid>`
+pub async fn list_all_stripped(prefix: &str) -> MyResult<Vec<String>> {
+    Ok(list_all(prefix).await?.into_iter()
+        .map(|k| k[prefix.len()..].to_owned())
+        .collect())
+}
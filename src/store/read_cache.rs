@@ -0,0 +1,38 @@
+// Request-scoped memoization of reads
+// Rendering a page often needs the same key more than once (e.g. the
+// post route looks up a post for redirection, then again for rendering),
+// and every read is a round-trip to the backend. Within a request,
+// string values read from the backend are remembered here, and any
+// write to a key through `store` forgets it.
+// Outside of `with_read_cache` (e.g. in tasks passed to `waitUntil`),
+// nothing is cached.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+
+crate::task_local! {
+    // None as a value means the key is known not to exist
+    static READ_CACHE: RefCell<HashMap<String, Option<String>>>;
+}
+
+// Run `f` with a fresh read cache, dropped when `f` completes
+pub async fn with_read_cache<F: Future>(f: F) -> F::Output {
+    READ_CACHE.scope(RefCell::new(HashMap::new()), f).await
+}
+
+// Some(_) if the key has been read during the current request
+pub fn get(key: &str) -> Option<Option<String>> {
+    READ_CACHE.try_with(|cache| cache.borrow().get(key).cloned()).ok().flatten()
+}
+
+pub fn remember(key: &str, value: &Option<String>) {
+    let _ = READ_CACHE.try_with(|cache| {
+        cache.borrow_mut().insert(key.to_owned(), value.clone());
+    });
+}
+
+pub fn invalidate(key: &str) {
+    let _ = READ_CACHE.try_with(|cache| {
+        cache.borrow_mut().remove(key);
+    });
+}
@@ -0,0 +1,292 @@
+// Storage backend for S3-compatible object storage
+// (AWS S3, MinIO, Backblaze B2, etc.)
+// Every key in the store becomes one object in the bucket, so the
+// key layout is exactly the same as in KV. Requests are signed with
+// AWS Signature Version 4 and sent through the global `fetch`.
+// Only path-style addressing (`<endpoint>/<bucket>/<key>`) is used,
+// since it is what every S3-compatible service supports.
+use crate::utils::*;
+use js_sys::{ArrayBuffer, Date, Uint8Array};
+use serde::Deserialize;
+use super::{BinaryValue, ListResult, Storage, StoreFuture, StreamValue};
+use std::vec::Vec;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::*;
+
+#[derive(Deserialize, Clone)]
+pub struct S3Config {
+    // Base URL of the S3 API, without the trailing "/"
+    // e.g. "https://s3.us-east-1.amazonaws.com" or "http://localhost:9000"
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key_id: String,
+    pub secret_access_key: String
+}
+
+pub struct S3Storage {
+    config: S3Config
+}
+
+// Percent-encode a string as required by SigV4
+// Everything except unreserved characters is encoded;
+// "/" is kept as-is for object paths
+fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut ret = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' =>
+                ret.push(b as char),
+            b'/' if !encode_slash => ret.push('/'),
+            _ => ret.push_str(&format!("%{:02X}", b))
+        }
+    }
+    ret
+}
+
+// Returns the current time in the format of `x-amz-date` (YYYYMMDD'T'HHMMSS'Z')
+fn amz_date_now() -> String {
+    // "YYYY-MM-DDTHH:MM:SS.sssZ"
+    let iso: String = Date::new_0().to_iso_string().into();
+    format!("{}{}{}T{}{}{}Z",
+        &iso[0..4], &iso[5..7], &iso[8..10], &iso[11..13], &iso[14..16], &iso[17..19])
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Extract the text content of every `<tag>...</tag>` in `xml`
+// S3 responses are simple enough that we don't need a real XML parser
+fn xml_tag_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut ret = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        match rest.find(&close) {
+            Some(end) => {
+                ret.push(xml_unescape(&rest[0..end]));
+                rest = &rest[end + close.len()..];
+            },
+            None => break
+        }
+    }
+    ret
+}
+
+impl S3Storage {
+    pub fn new(config: S3Config) -> S3Storage {
+        S3Storage { config }
+    }
+
+    // Compute the `Authorization` header for a request
+    // `canonical_uri` and `canonical_query` must already be URI-encoded,
+    // and `headers` must contain every header to be signed (including `host`)
+    // with lowercase names.
+    pub async fn authorization(
+        &self, method: &str, canonical_uri: &str, canonical_query: &str,
+        headers: &[(String, String)], payload_hash: &str, amz_date: &str
+    ) -> String {
+        let mut headers = headers.to_vec();
+        headers.sort();
+        let canonical_headers = headers.iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect::<String>();
+        let signed_headers = headers.iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<&str>>()
+            .join(";");
+        let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}",
+            method, canonical_uri, canonical_query, canonical_headers, signed_headers, payload_hash);
+
+        let date = &amz_date[0..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date, scope, hex::encode(sha256(canonical_request.as_bytes()).await));
+
+        let key = format!("AWS4{}", self.config.secret_access_key);
+        let key = hmac_sha256(key.as_bytes(), date.as_bytes()).await;
+        let key = hmac_sha256(&key, self.config.region.as_bytes()).await;
+        let key = hmac_sha256(&key, b"s3").await;
+        let key = hmac_sha256(&key, b"aws4_request").await;
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()).await);
+
+        format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key_id, scope, signed_headers, signature)
+    }
+
+    // Send a signed request to the object `key`, or to the bucket itself if `key` is None
+    async fn send(
+        &self, method: &str, key: Option<&str>,
+        mut query: Vec<(String, String)>, body: Option<&[u8]>, content_type: Option<&str>
+    ) -> MyResult<Response> {
+        let endpoint = Url::new(&self.config.endpoint).internal_err()?;
+        let path = match key {
+            Some(key) => format!("/{}/{}", self.config.bucket, uri_encode(key, false)),
+            None => format!("/{}", self.config.bucket)
+        };
+        query.sort();
+        let query = query.iter()
+            .map(|(k, v)| format!("{}={}", uri_encode(k, true), uri_encode(v, true)))
+            .collect::<Vec<String>>()
+            .join("&");
+
+        let payload_hash = hex::encode(sha256(body.unwrap_or(&[])).await);
+        let amz_date = amz_date_now();
+        let signed_headers = vec![
+            ("host".to_owned(), endpoint.host()),
+            ("x-amz-content-sha256".to_owned(), payload_hash.clone()),
+            ("x-amz-date".to_owned(), amz_date.clone())
+        ];
+        let authorization = self.authorization(
+            method, &path, &query, &signed_headers, &payload_hash, &amz_date).await;
+
+        let mut init = RequestInit::new();
+        init.method(method);
+        // `host` is filled in by fetch
+        let headers = headers!{
+            "x-amz-content-sha256" => &payload_hash,
+            "x-amz-date" => &amz_date,
+            "Authorization" => &authorization
+        };
+        // Not signed; S3 keeps it and sends it back on GET
+        if let Some(content_type) = content_type {
+            headers.set("Content-Type", content_type).internal_err()?;
+        }
+        init.headers(headers.as_ref());
+        if let Some(body) = body {
+            init.body(Some(&Uint8Array::from(body)));
+        }
+
+        let url = if query.is_empty() {
+            format!("{}{}", endpoint.origin(), path)
+        } else {
+            format!("{}{}?{}", endpoint.origin(), path, query)
+        };
+        let req = Request::new_with_str_and_init(&url, &init).internal_err()?;
+        Ok(JsFuture::from(crate::fetch(&req)).await.internal_err()?.into())
+    }
+
+    // Returns None on 404
+    async fn get_object_response(&self, key: &str) -> MyResult<Option<Response>> {
+        let resp = self.send("GET", Some(key), vec![], None, None).await?;
+        if resp.status() == 404 {
+            return Ok(None);
+        } else if !resp.ok() {
+            return Err(Error::InternalError());
+        }
+        Ok(Some(resp))
+    }
+
+    async fn get_object(&self, key: &str) -> MyResult<Option<BinaryValue>> {
+        let resp = match self.get_object_response(key).await? {
+            Some(resp) => resp,
+            None => return Ok(None)
+        };
+        let buffer: ArrayBuffer = JsFuture::from(resp.array_buffer().internal_err()?)
+            .await.internal_err()?.into();
+        Ok(Some(BinaryValue {
+            bytes: Uint8Array::new(&buffer).to_vec(),
+            content_type: resp.headers().get("content-type").internal_err()?
+        }))
+    }
+
+    async fn put_object(&self, key: &str, value: &[u8], content_type: Option<&str>) -> MyResult<()> {
+        let resp = self.send("PUT", Some(key), vec![], Some(value), content_type).await?;
+        if !resp.ok() {
+            return Err(Error::InternalError());
+        }
+        Ok(())
+    }
+}
+
+impl Storage for S3Storage {
+    fn get_str<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            match self.get_object(key).await? {
+                Some(value) => Ok(Some(String::from_utf8(value.bytes).internal_err()?)),
+                None => Ok(None)
+            }
+        })
+    }
+
+    fn get_bytes<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<BinaryValue>> {
+        Box::pin(self.get_object(key))
+    }
+
+    fn put_str<'a>(&'a self, key: &'a str, value: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(self.put_object(key, value.as_bytes(), None))
+    }
+
+    fn put_bytes<'a>(
+        &'a self, key: &'a str, value: &'a [u8], content_type: Option<&'a str>
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(self.put_object(key, value, content_type))
+    }
+
+    // Uploads still go through `put_bytes`, since SigV4 needs
+    // the hash of the whole payload before sending anything
+    fn get_stream<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<StreamValue>> {
+        Box::pin(async move {
+            let resp = match self.get_object_response(key).await? {
+                Some(resp) => resp,
+                None => return Ok(None)
+            };
+            Ok(Some(StreamValue {
+                stream: resp.body().ok_or(Error::InternalError())?,
+                content_type: resp.headers().get("content-type").internal_err()?
+            }))
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            // S3 returns 204 even if the object does not exist
+            let resp = self.send("DELETE", Some(key), vec![], None, None).await?;
+            if !resp.ok() {
+                return Err(Error::InternalError());
+            }
+            Ok(())
+        })
+    }
+
+    fn list<'a>(
+        &'a self, prefix: &'a str, cursor: Option<&'a str>, limit: usize
+    ) -> StoreFuture<'a, ListResult> {
+        Box::pin(async move {
+            // ListObjectsV2
+            let mut query = vec![
+                ("list-type".to_owned(), "2".to_owned()),
+                ("prefix".to_owned(), prefix.to_owned()),
+                ("max-keys".to_owned(), limit.to_string())
+            ];
+            if let Some(cursor) = cursor {
+                query.push(("continuation-token".to_owned(), cursor.to_owned()));
+            }
+
+            let resp = self.send("GET", None, query, None, None).await?;
+            if !resp.ok() {
+                return Err(Error::InternalError());
+            }
+            let xml = JsFuture::from(resp.text().internal_err()?).await.internal_err()?
+                .as_string().ok_or(Error::InternalError())?;
+
+            let truncated = xml_tag_values(&xml, "IsTruncated").first()
+                .map(|s| s == "true").unwrap_or(false);
+            Ok(ListResult {
+                keys: xml_tag_values(&xml, "Key"),
+                cursor: if truncated {
+                    xml_tag_values(&xml, "NextContinuationToken").into_iter().next()
+                } else {
+                    None
+                }
+            })
+        })
+    }
+}
@@ -0,0 +1,65 @@
+// Multiple authors
+use super::*;
+use crate::utils::Author;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test]
+async fn unknown_authors_are_rejected() {
+    let storage = setup();
+
+    let text = with_metadata(json!({ "author": "surely-not-in-config-1234" }), "Content");
+    assert_eq!(publish("0001-aaaa", "Hello", &text).await.status(), 400);
+    assert!(storage.keys().is_empty());
+    assert_eq!(get("/author/surely-not-in-config-1234/").await.status(), 404);
+}
+
+fn author(name: &str, email: Option<&str>) -> Author {
+    Author {
+        name: name.into(),
+        bio: Some(format!("{} writes here", name)),
+        avatar: None,
+        url: None,
+        email: email.map(|e| e.into())
+    }
+}
+
+#[wasm_bindgen_test]
+async fn author_pages_list_their_posts() {
+    setup();
+    override_config(|config| {
+        config.authors.insert("alice".into(), author("Alice", Some("alice@example.com")));
+        config.authors.insert("bob".into(), author("Bob", None));
+    });
+
+    let text = with_metadata(json!({ "author": "alice" }), "Content");
+    assert_eq!(publish("0001-aaaa", "By Alice", &text).await.status(), 200);
+    assert_eq!(publish("0002-bbbb", "By nobody", "Content").await.status(), 200);
+
+    let html = response_text(&get("/author/alice/").await).await;
+    assert!(html.contains("Alice writes here"));
+    assert!(html.contains("By Alice"));
+    assert!(!html.contains("By nobody"));
+    assert!(response_text(&get("/0001/by-alice/").await).await
+        .contains("<a class=\"author\" href=\"/author/alice/\">Alice</a>"));
+    assert_eq!(get("/author/bob/").await.status(), 404);
+    let rss = response_text(&get("/feed.xml").await).await;
+    assert_eq!(rss.matches("<dc:creator>").count(), 1);
+    assert!(rss.contains("<dc:creator>Alice</dc:creator>"));
+    assert!(rss.contains("<author>alice@example.com (Alice)</author>"));
+
+    // Changing the author moves the post to the other author's page
+    let text = with_metadata(json!({ "author": "bob" }), "Content");
+    assert_eq!(publish("0001-aaaa", "By Alice", &text).await.status(), 200);
+    assert_eq!(get("/author/alice/").await.status(), 404);
+    assert!(response_text(&get("/author/bob/").await).await.contains("By Alice"));
+    let rss = response_text(&get("/feed.xml").await).await;
+    assert!(rss.contains("<dc:creator>Bob</dc:creator>"));
+    // Without an email, there is no `<author>`
+    assert!(!rss.contains("<author>"));
+
+    // Dropping the author takes the post off the author's page
+    assert_eq!(publish("0001-aaaa", "By Alice", "Content").await.status(), 200);
+    assert_eq!(get("/author/bob/").await.status(), 404);
+    assert!(!response_text(&get("/feed.xml").await).await.contains("<dc:creator>"));
+}
@@ -0,0 +1,146 @@
+// Exporting the whole blog as an archive
+use super::*;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test]
+async fn export_empty_blog() {
+    setup();
+
+    let resp = get(&with_secret("/export")).await;
+    assert_eq!(resp.status(), 200);
+    let archive: serde_json::Value = serde_json::from_str(&response_text(&resp).await).unwrap();
+    assert_eq!(archive["posts"], json!([]));
+    assert_eq!(archive["url_mappings"], json!({}));
+    assert_eq!(archive["cache_whitelist"], json!([]));
+}
+
+#[wasm_bindgen_test]
+async fn export_includes_everything() {
+    setup();
+
+    assert_eq!(publish("0001-aaaa", "First", "![img](https://example.com/a.png)").await.status(), 200);
+    let text = with_metadata(json!({ "url": "old-url", "unlist": true }), "Content");
+    assert_eq!(publish("0002-bbbb", "Second", &text).await.status(), 200);
+    let text = with_metadata(json!({ "url": "new-url", "unlist": true }), "Content");
+    assert_eq!(publish("0002-bbbb", "Second", &text).await.status(), 200);
+
+    assert_eq!(get("/export").await.status(), 400);
+    assert_eq!(get("/export?secret=wrong").await.status(), 401);
+    let resp = get(&with_secret("/export")).await;
+    assert_eq!(resp.status(), 200);
+    let archive: serde_json::Value = serde_json::from_str(&response_text(&resp).await).unwrap();
+
+    assert_eq!(archive["version"], 1);
+    assert_eq!(archive["posts_list"], json!(["0001-aaaa"]));
+    let posts = archive["posts"].as_array().unwrap();
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[1]["uuid"], "0002-bbbb");
+    assert_eq!(posts[1]["url"], "new-url");
+    assert_eq!(archive["url_mappings"], json!({
+        "0001/first": "0001-aaaa",
+        "old-url": "0002-bbbb",
+        "new-url": "0002-bbbb"
+    }));
+    assert_eq!(archive["cache_whitelist"], json!(["https://example.com/a.png"]));
+}
+
+async fn import(archive: &str, dry_run: bool) -> (u16, serde_json::Value) {
+    let path = if dry_run { "/import?dry_run=true" } else { "/import" };
+    let resp = request("POST", &with_secret(path), Some(archive)).await;
+    let report = serde_json::from_str(&response_text(&resp).await).unwrap_or(json!(null));
+    (resp.status(), report)
+}
+
+#[wasm_bindgen_test]
+async fn import_restores_exported_blog() {
+    setup();
+    assert_eq!(publish("0001-aaaa", "First", "Content").await.status(), 200);
+    let text = with_metadata(json!({ "url": "old-url" }), "Content");
+    assert_eq!(publish("0002-bbbb", "Second", &text).await.status(), 200);
+    let text = with_metadata(json!({ "url": "new-url" }), "Content");
+    assert_eq!(publish("0002-bbbb", "Second", &text).await.status(), 200);
+    let archive = response_text(&get(&with_secret("/export")).await).await;
+
+    // Move to an empty blog
+    let storage = setup();
+    let (status, report) = import(&archive, true).await;
+    assert_eq!(status, 200);
+    assert_eq!(report["imported"], false);
+    assert_eq!(report["posts"], 2);
+    assert!(storage.keys().is_empty());
+
+    let (status, report) = import(&archive, false).await;
+    assert_eq!(status, 200);
+    assert_eq!(report["imported"], true);
+    assert_eq!(posts_list().await, vec!["0002-bbbb".to_owned(), "0001-aaaa".to_owned()]);
+    assert!(storage.keys().contains(&"content_cache_0001-aaaa".to_owned()));
+    assert_eq!(get("/0001/first/").await.status(), 200);
+    assert_eq!(get("/new-url/").await.status(), 200);
+    let resp = get("/old-url/").await;
+    assert_eq!(resp.status(), 301);
+    assert_eq!(location(&resp), format!("{}/new-url/", BASE_URL));
+
+    // Importing the same archive again clashes with itself
+    let (status, report) = import(&archive, false).await;
+    assert_eq!(status, 409);
+    assert_eq!(report["imported"], false);
+    let kinds: Vec<&str> = report["conflicts"].as_array().unwrap().iter()
+        .map(|c| c["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, vec!["uuid_exists", "uuid_exists"]);
+}
+
+#[wasm_bindgen_test]
+async fn import_keeps_schedule() {
+    setup();
+    let text = with_metadata(json!({ "publish_at": "2100-01-01" }), "Content");
+    assert_eq!(publish("0001-aaaa", "Scheduled", &text).await.status(), 200);
+    let text = with_metadata(json!({ "publish_at": "2100-01-01", "unlist": true }), "Content");
+    assert_eq!(publish("0002-bbbb", "Unlisted", &text).await.status(), 200);
+    let archive = response_text(&get(&with_secret("/export")).await).await;
+    let parsed: serde_json::Value = serde_json::from_str(&archive).unwrap();
+    assert_eq!(parsed["pending_posts"], json!(["0001-aaaa"]));
+
+    setup();
+    assert_eq!(import(&archive, false).await.0, 200);
+    let pending: serde_json::Value = store::get_obj("pending_posts").await.ok().unwrap();
+    assert_eq!(pending["posts"], json!(["0001-aaaa"]));
+    assert!(posts_list().await.is_empty());
+}
+
+#[wasm_bindgen_test]
+async fn import_reports_conflicts_before_writing() {
+    let storage = setup();
+    assert_eq!(publish("0003-cccc", "Existing", "Content").await.status(), 200);
+    let keys_before = storage.keys();
+
+    let post = |uuid: &str, url: &str| json!({
+        "uuid": uuid, "timestamp": 0, "url": url, "title": "T", "content": "C", "theme_config": null
+    });
+    let archive = json!({
+        "version": 1,
+        "created_at": 0,
+        "posts": [post("0001-aaaa", "same"), post("0002-bbbb", "same"), post("0002-bbbb", "other"),
+            post("0004-dddd", "0003/existing")],
+        "posts_list": ["0001-aaaa", "0005-eeee"],
+        "url_mappings": {},
+        "cache_whitelist": []
+    }).to_string();
+
+    for dry_run in vec![true, false] {
+        let (status, report) = import(&archive, dry_run).await;
+        assert_eq!(status, 409);
+        assert_eq!(report["imported"], false);
+        let conflicts: Vec<(&str, &str)> = report["conflicts"].as_array().unwrap().iter()
+            .map(|c| (c["kind"].as_str().unwrap(), c["uuid"].as_str().unwrap()))
+            .collect();
+        assert_eq!(conflicts, vec![
+            ("duplicate_uuid", "0002-bbbb"),
+            ("unknown_post", "0005-eeee"),
+            ("url_taken", "0004-dddd"),
+            ("duplicate_url", "0002-bbbb")
+        ]);
+        assert_eq!(storage.keys(), keys_before);
+    }
+}
@@ -0,0 +1,104 @@
+// Optimistic concurrency for writes to the posts list and posts
+use super::*;
+use serde_json::json;
+use std::cell::RefCell;
+use wasm_bindgen_test::*;
+
+// Another "device" writes to `key` right after each of our
+// writes to it, as if our write had raced with theirs
+struct Racing {
+    key: String,
+    // Given the value we have just written, returns the value written by
+    // the other device, if it writes anything at all this time
+    competitor: RefCell<Box<dyn FnMut(&str) -> Option<String>>>
+}
+
+impl StorageHooks for Racing {
+    fn put_str<'a>(
+        &'a self, inner: &'a MemoryStorage, key: &'a str, value: &'a str
+    ) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            inner.put_str(key, value).await?;
+            if key == self.key {
+                let competing = (self.competitor.borrow_mut())(value);
+                if let Some(competing) = competing {
+                    inner.put_str(key, &competing).await?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+fn setup_racing(key: &str, competitor: impl FnMut(&str) -> Option<String> + 'static) {
+    setup_wrapped(Racing {
+        key: key.into(),
+        competitor: RefCell::new(Box::new(competitor))
+    });
+}
+
+#[wasm_bindgen_test]
+async fn concurrent_publish_is_not_lost() {
+    // The other device publishes 0009-zzzz based on the same (empty) list as us
+    let mut fired = false;
+    setup_racing("posts_list", move |_| {
+        if fired {
+            return None;
+        }
+        fired = true;
+        Some(json!({ "version": 1, "posts": ["0009-zzzz"] }).to_string())
+    });
+
+    assert_eq!(publish("0001-aaaa", "First", "Content").await.status(), 200);
+    assert_eq!(posts_list().await, vec!["0001-aaaa".to_owned(), "0009-zzzz".to_owned()]);
+}
+
+#[wasm_bindgen_test]
+async fn endless_contention_is_reported_as_conflict() {
+    let mut version = 100;
+    setup_racing("posts_list", move |_| {
+        version += 1;
+        Some(json!({ "version": version, "posts": ["0009-zzzz"] }).to_string())
+    });
+
+    let resp = publish("0001-aaaa", "First", "Content").await;
+    assert_eq!(resp.status(), 409);
+    assert!(response_text(&resp).await.contains("posts_list"));
+    assert_eq!(posts_list().await, vec!["0009-zzzz".to_owned()]);
+}
+
+#[wasm_bindgen_test]
+async fn concurrent_post_update_is_reapplied() {
+    // The other device updates the title at the same time as our first publish
+    let mut fired = false;
+    setup_racing("post_by_uuid_0001-aaaa", move |ours| {
+        if fired {
+            return None;
+        }
+        fired = true;
+        let mut theirs: serde_json::Value = serde_json::from_str(ours).unwrap();
+        theirs["title"] = json!("Other device");
+        Some(theirs.to_string())
+    });
+
+    assert_eq!(publish("0001-aaaa", "This device", "Content").await.status(), 200);
+    let post: serde_json::Value = store::get_obj("post_by_uuid_0001-aaaa").await
+        .unwrap_or(serde_json::Value::Null);
+    assert_eq!(post["title"], "This device");
+    assert_eq!(post["version"], 2);
+}
+
+#[wasm_bindgen_test]
+async fn republishing_leaves_lists_alone() {
+    setup();
+    let text = with_metadata(json!({ "tags": ["rust"] }), "Content");
+    assert_eq!(publish("0001-aaaa", "First", &text).await.status(), 200);
+    assert_eq!(publish("0001-aaaa", "First, edited", &text).await.status(), 200);
+
+    // Neither list changed, so neither was written again
+    for key in &["posts_list", "tag_posts_rust"] {
+        let list: serde_json::Value = store::get_obj(key).await
+            .unwrap_or(serde_json::Value::Null);
+        assert_eq!(list["version"], 1, "{} was rewritten", key);
+    }
+}
@@ -0,0 +1,154 @@
+// The publish -> render -> delete lifecycle of posts
+use super::*;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test]
+async fn publish_render_delete() {
+    let storage = setup();
+
+    let resp = publish("0001-aaaa", "Hello World", "Some *content*").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(posts_list().await, vec!["0001-aaaa".to_owned()]);
+
+    let resp = get("/").await;
+    assert_eq!(resp.status(), 200);
+    let html = response_text(&resp).await;
+    assert!(html.contains("Hello World"));
+    // Absolute, so that the links also work when `home.hbs` renders tag or author pages
+    assert!(html.contains("<a href=\"/0001/hello-world/\">Hello World</a>"));
+
+    let resp = get("/0001/hello-world/").await;
+    assert_eq!(resp.status(), 200);
+    assert!(response_text(&resp).await.contains("<em>content</em>"));
+
+    let resp = delete("0001-aaaa").await;
+    assert_eq!(resp.status(), 200);
+    assert!(posts_list().await.is_empty());
+    assert!(!storage.keys().iter().any(|k| k.starts_with("post_by_uuid_")));
+    assert!(!storage.keys().iter().any(|k| k.starts_with("content_cache_")));
+
+    let resp = get("/0001/hello-world/").await;
+    assert_eq!(resp.status(), 410);
+}
+
+#[wasm_bindgen_test]
+async fn unauthenticated_publish_is_rejected() {
+    let storage = setup();
+
+    let body = sn_body("0001-aaaa", "Hello World", "Content");
+    let resp = request("POST", "/post?secret=wrong", Some(&body)).await;
+    assert_eq!(resp.status(), 401);
+    let resp = request("POST", "/post", Some(&body)).await;
+    assert_eq!(resp.status(), 400);
+    assert!(storage.keys().is_empty());
+}
+
+#[wasm_bindgen_test]
+async fn url_change_redirects_old_url() {
+    setup();
+
+    let text = with_metadata(json!({ "url": "first-url" }), "Content");
+    assert_eq!(publish("0001-aaaa", "Hello", &text).await.status(), 200);
+    assert_eq!(get("/first-url/").await.status(), 200);
+
+    let text = with_metadata(json!({ "url": "second-url" }), "Content");
+    assert_eq!(publish("0001-aaaa", "Hello", &text).await.status(), 200);
+
+    let resp = get("/first-url/").await;
+    assert_eq!(resp.status(), 301);
+    assert_eq!(location(&resp), format!("{}/second-url/", BASE_URL));
+    assert_eq!(get("/second-url/").await.status(), 200);
+
+    // Paths without the trailing slash are normalized first
+    let resp = get("/second-url").await;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(location(&resp), format!("{}/second-url/", BASE_URL));
+}
+
+#[wasm_bindgen_test]
+async fn deleted_posts_leave_tombstones() {
+    let storage = setup();
+
+    for url in &["first-url", "second-url", "third-url"] {
+        let text = with_metadata(json!({ "url": url }), "Content");
+        assert_eq!(publish("0001-aaaa", "Hello", &text).await.status(), 200);
+    }
+    let post: serde_json::Value = store::get_obj("post_by_uuid_0001-aaaa").await.ok().unwrap();
+    assert_eq!(post["url_history"], json!(["first-url", "second-url"]));
+
+    // An old URL taken over by another post is not touched
+    let text = with_metadata(json!({ "url": "first-url" }), "Content");
+    assert_eq!(publish("0002-bbbb", "Other", &text).await.status(), 200);
+
+    assert_eq!(delete("0001-aaaa").await.status(), 200);
+    assert!(!storage.keys().iter().any(|k| k.starts_with("url_mapping_") && !k.ends_with("first-url")));
+    assert_eq!(get("/second-url/").await.status(), 410);
+    assert_eq!(get("/third-url/").await.status(), 410);
+    assert_eq!(get("/first-url/").await.status(), 200);
+    assert_eq!(get("/never-existed/").await.status(), 404);
+
+    // The URL of a deleted post can be reused
+    let text = with_metadata(json!({ "url": "third-url" }), "Content");
+    assert_eq!(publish("0003-cccc", "New", &text).await.status(), 200);
+    assert_eq!(get("/third-url/").await.status(), 200);
+    assert!(!storage.keys().contains(&"url_tombstone_third-url".to_owned()));
+}
+
+#[wasm_bindgen_test]
+async fn dangling_mappings_are_gone() {
+    setup();
+
+    // Left behind by deletes before tombstones existed
+    assert!(store::put_str("url_mapping_old-post", "0009-zzzz").await.is_ok());
+    assert_eq!(get("/old-post/").await.status(), 410);
+}
+
+#[wasm_bindgen_test]
+async fn unlisted_posts_stay_reachable() {
+    setup();
+
+    assert_eq!(publish("0001-aaaa", "First", "Content").await.status(), 200);
+    let text = with_metadata(json!({ "unlist": true }), "Content");
+    assert_eq!(publish("0002-bbbb", "Second", &text).await.status(), 200);
+    assert_eq!(posts_list().await, vec!["0001-aaaa".to_owned()]);
+    assert_eq!(get("/0002/second/").await.status(), 200);
+    assert!(!response_text(&get("/").await).await.contains("Second"));
+
+    // Publishing again without the flag lists it on top
+    assert_eq!(publish("0002-bbbb", "Second", "Content").await.status(), 200);
+    assert_eq!(posts_list().await, vec!["0002-bbbb".to_owned(), "0001-aaaa".to_owned()]);
+
+    // `unlisted` works the same as `unlist`
+    let text = with_metadata(json!({ "unlisted": true }), "Content");
+    assert_eq!(publish("0002-bbbb", "Second", &text).await.status(), 200);
+    assert_eq!(posts_list().await, vec!["0001-aaaa".to_owned()]);
+    assert_eq!(get("/0002/second/").await.status(), 200);
+}
+
+#[wasm_bindgen_test]
+async fn corrupted_posts_list_is_never_overwritten() {
+    let storage = setup();
+
+    assert_eq!(publish("0001-aaaa", "First", "Content").await.status(), 200);
+    let broken = "[\"0001-aaaa\",";
+    assert!(store::put_str("posts_list", broken).await.is_ok());
+
+    let resp = publish("0002-bbbb", "Second", "Content").await;
+    assert_eq!(resp.status(), 500);
+    let reason = response_text(&resp).await;
+    assert!(reason.contains("posts_list_recovery_"));
+    assert_eq!(delete("0001-aaaa").await.status(), 500);
+    assert_eq!(get("/").await.status(), 500);
+
+    // The list is left alone, and exactly one backup of it exists
+    assert_eq!(store::get_str("posts_list").await.ok().unwrap(), broken);
+    let backups: Vec<String> = storage.keys().into_iter()
+        .filter(|k| k.starts_with("posts_list_recovery_"))
+        .collect();
+    assert_eq!(backups.len(), 1);
+    assert!(reason.contains(&backups[0]));
+    assert_eq!(store::get_str(&backups[0]).await.ok().unwrap(), broken);
+    // Nothing from the failed publish was written
+    assert!(!storage.keys().contains(&"post_by_uuid_0002-bbbb".to_owned()));
+}
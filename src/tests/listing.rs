@@ -0,0 +1,42 @@
+// Prefix listing in the store module
+use super::*;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test]
+async fn list_paginates_by_prefix() {
+    setup();
+
+    for i in 0..(store::LIST_PAGE_SIZE + 10) {
+        assert!(store::put_str(&format!("a_{:05}", i), "").await.is_ok());
+    }
+    assert!(store::put_str("b_0", "").await.is_ok());
+
+    let page = store::list("a_", None).await.ok().unwrap();
+    assert_eq!(page.keys.len(), store::LIST_PAGE_SIZE);
+    assert_eq!(page.keys[0], "a_00000");
+    let cursor = page.cursor.unwrap();
+    let page = store::list("a_", Some(&cursor)).await.ok().unwrap();
+    assert_eq!(page.keys.len(), 10);
+    assert!(page.cursor.is_none());
+
+    let all = store::list_all("a_").await.ok().unwrap();
+    assert_eq!(all.len(), store::LIST_PAGE_SIZE + 10);
+    assert_eq!(store::list_all("b_").await.ok().unwrap(), vec!["b_0".to_owned()]);
+    assert!(store::list_all("c_").await.ok().unwrap().is_empty());
+}
+
+#[wasm_bindgen_test]
+async fn hidden_posts_can_be_enumerated() {
+    setup();
+
+    assert_eq!(publish("0001-aaaa", "Listed", "Content").await.status(), 200);
+    let text = with_metadata(json!({ "unlist": true }), "Content");
+    assert_eq!(publish("0002-bbbb", "Hidden", &text).await.status(), 200);
+
+    assert_eq!(posts_list().await, vec!["0001-aaaa".to_owned()]);
+    assert_eq!(store::list_all_stripped(crate::blog::POST_KEY_PREFIX).await.ok().unwrap(),
+        vec!["0001-aaaa".to_owned(), "0002-bbbb".to_owned()]);
+    assert_eq!(store::list_all_stripped(crate::blog::URL_MAPPING_KEY_PREFIX).await.ok().unwrap(),
+        vec!["0001/listed".to_owned(), "0002/hidden".to_owned()]);
+}
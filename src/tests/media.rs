@@ -0,0 +1,63 @@
+// Media files hosted under /media/
+use super::*;
+use js_sys::{ArrayBuffer, Uint8Array};
+use wasm_bindgen_test::*;
+
+const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0xff];
+
+async fn upload(path: &str, bytes: &[u8], content_type: Option<&str>) -> Response {
+    let mut init = RequestInit::new();
+    init.method("PUT");
+    init.body(Some(&Uint8Array::from(bytes)));
+    let headers = Headers::new().unwrap();
+    if let Some(content_type) = content_type {
+        headers.set("Content-Type", content_type).unwrap();
+    }
+    init.headers(headers.as_ref());
+    let req = Request::new_with_str_and_init(&format!("{}{}", BASE_URL, path), &init).unwrap();
+    crate::handle_request_rs(fake_event(), req).await
+}
+
+async fn response_bytes(resp: &Response) -> Vec<u8> {
+    let buffer: ArrayBuffer = JsFuture::from(resp.array_buffer().unwrap()).await.unwrap().into();
+    Uint8Array::new(&buffer).to_vec()
+}
+
+#[wasm_bindgen_test]
+async fn upload_serve_delete() {
+    let storage = setup();
+
+    let resp = upload(&with_secret("/media/cat.png"), PNG, Some("image/png")).await;
+    assert_eq!(resp.status(), 200);
+    assert!(storage.keys().contains(&"media_cat.png".to_owned()));
+
+    let resp = get("/media/cat.png").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap().unwrap(), "image/png");
+    assert_eq!(resp.headers().get("cache-control").unwrap().unwrap(), "public, max-age=300");
+    assert_eq!(response_bytes(&resp).await, PNG.to_vec());
+
+    let resp = request("DELETE", &with_secret("/media/cat.png"), None).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(get("/media/cat.png").await.status(), 404);
+}
+
+#[wasm_bindgen_test]
+async fn content_type_is_guessed_from_name() {
+    setup();
+
+    assert_eq!(upload(&with_secret("/media/a%20b.jpg"), PNG, None).await.status(), 200);
+    let resp = get("/media/a%20b.jpg").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap().unwrap(), "image/jpeg");
+}
+
+#[wasm_bindgen_test]
+async fn unauthenticated_upload_is_rejected() {
+    let storage = setup();
+
+    assert_eq!(upload("/media/cat.png?secret=wrong", PNG, None).await.status(), 401);
+    assert_eq!(upload("/media/cat.png", PNG, None).await.status(), 400);
+    assert_eq!(request("DELETE", "/media/cat.png", None).await.status(), 400);
+    assert!(storage.keys().is_empty());
+}
@@ -0,0 +1,191 @@
+// Test harness for driving Paprika end-to-end without a Worker
+// Tests run under Node via `wasm-pack test --node`, with the
+// in-memory storage backend standing in for Workers KV.
+// Requests are fed through `handle_request_rs` exactly like
+// the Worker entry point would do.
+use crate::store::{self, BinaryValue, ListResult, MemoryStorage, Storage, StoreFuture};
+use crate::utils::Config;
+use js_sys::{Function, Object, Reflect};
+use std::cell::Cell;
+use std::rc::Rc;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::*;
+
+mod authors;
+mod backup;
+mod concurrency;
+mod lifecycle;
+mod listing;
+mod media;
+mod neighbours;
+mod ordering;
+mod pages;
+mod pinning;
+mod read_cache;
+mod rendering;
+mod revisions;
+mod s3;
+mod schedule;
+mod series;
+mod tags;
+
+pub const BASE_URL: &'static str = "https://blog.example.com";
+
+thread_local! {
+    // Replaces config.json until the next `setup`
+    static CONFIG_OVERRIDE: Cell<Option<&'static Config>> = Cell::new(None);
+}
+
+pub fn config_override() -> Option<&'static Config> {
+    CONFIG_OVERRIDE.with(|c| c.get())
+}
+
+// Run the rest of the test with a modified copy of config.json,
+// so that tests don't depend on how the blog under test is configured
+// Must be called after `setup`, which goes back to config.json
+pub fn override_config(f: impl FnOnce(&mut Config)) {
+    let mut config = crate::load_config();
+    f(&mut config);
+    // `CONFIG` hands out `'static` references, so this is leaked
+    // (only a few small copies over the whole test run)
+    CONFIG_OVERRIDE.with(|c| c.set(Some(Box::leak(Box::new(config)))));
+}
+
+// Install a fresh, empty in-memory backend
+// Every test should call this first so that tests don't see each other's data
+pub fn setup() -> Rc<MemoryStorage> {
+    CONFIG_OVERRIDE.with(|c| c.set(None));
+    let storage = Rc::new(MemoryStorage::new());
+    store::set_backend(storage.clone());
+    storage
+}
+
+// What a `WrappedStorage` does on string reads and writes
+// Both default to simply going to the in-memory backend
+pub trait StorageHooks: 'static {
+    fn get_str<'a>(&'a self, inner: &'a MemoryStorage, key: &'a str) -> StoreFuture<'a, Option<String>> {
+        inner.get_str(key)
+    }
+
+    fn put_str<'a>(
+        &'a self, inner: &'a MemoryStorage, key: &'a str, value: &'a str
+    ) -> StoreFuture<'a, ()> {
+        inner.put_str(key, value)
+    }
+}
+
+// An in-memory backend for tests that need to watch or interfere with
+// string reads and writes, which is where all records and lists go.
+// Everything else is passed through untouched.
+pub struct WrappedStorage<H> {
+    pub inner: MemoryStorage,
+    pub hooks: H
+}
+
+impl<H: StorageHooks> Storage for WrappedStorage<H> {
+    fn get_str<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<String>> {
+        self.hooks.get_str(&self.inner, key)
+    }
+
+    fn get_bytes<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<BinaryValue>> {
+        self.inner.get_bytes(key)
+    }
+
+    fn put_str<'a>(&'a self, key: &'a str, value: &'a str) -> StoreFuture<'a, ()> {
+        self.hooks.put_str(&self.inner, key, value)
+    }
+
+    fn put_bytes<'a>(
+        &'a self, key: &'a str, value: &'a [u8], content_type: Option<&'a str>
+    ) -> StoreFuture<'a, ()> {
+        self.inner.put_bytes(key, value, content_type)
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        self.inner.delete(key)
+    }
+
+    fn list<'a>(
+        &'a self, prefix: &'a str, cursor: Option<&'a str>, limit: usize
+    ) -> StoreFuture<'a, ListResult> {
+        self.inner.list(prefix, cursor, limit)
+    }
+}
+
+// Like `setup`, but with a fresh, empty backend wrapped with `hooks`
+pub fn setup_wrapped<H: StorageHooks>(hooks: H) -> Rc<WrappedStorage<H>> {
+    setup();
+    let storage = Rc::new(WrappedStorage { inner: MemoryStorage::new(), hooks });
+    store::set_backend(storage.clone());
+    storage
+}
+
+// Node doesn't have ExtendableEvent; we only ever call `waitUntil` on it,
+// and the promises passed there are already running on the event loop anyway
+fn fake_event() -> ExtendableEvent {
+    let ev = Object::new();
+    Reflect::set(&ev, &"waitUntil".into(), &Function::new_with_args("promise", "")).unwrap();
+    ev.unchecked_into()
+}
+
+pub async fn request(method: &str, path: &str, body: Option<&str>) -> Response {
+    let mut init = RequestInit::new();
+    init.method(method);
+    if let Some(body) = body {
+        init.body(Some(&JsValue::from_str(body)));
+    }
+    let req = Request::new_with_str_and_init(&format!("{}{}", BASE_URL, path), &init).unwrap();
+    crate::handle_request_rs(fake_event(), req).await
+}
+
+pub async fn get(path: &str) -> Response {
+    request("GET", path, None).await
+}
+
+pub async fn response_text(resp: &Response) -> String {
+    JsFuture::from(resp.text().unwrap()).await.unwrap().as_string().unwrap()
+}
+
+pub fn location(resp: &Response) -> String {
+    resp.headers().get("location").unwrap().unwrap()
+}
+
+// Append the secret to an authenticated endpoint
+pub fn with_secret(path: &str) -> String {
+    let sep = if path.contains("?") { "&" } else { "?" };
+    format!("{}{}secret={}", path, sep, crate::CONFIG.secret)
+}
+
+// Build the POST body Standard Notes sends to action endpoints
+pub fn sn_body(uuid: &str, title: &str, text: &str) -> String {
+    serde_json::json!({
+        "items": [{
+            "uuid": uuid,
+            "content": {
+                "title": title,
+                "text": text
+            }
+        }]
+    }).to_string()
+}
+
+// Publish or update a note through the Standard Notes action endpoint
+pub async fn publish(uuid: &str, title: &str, text: &str) -> Response {
+    request("POST", &with_secret("/post"), Some(&sn_body(uuid, title, text))).await
+}
+
+pub async fn delete(uuid: &str) -> Response {
+    request("POST", &with_secret("/delete"), Some(&sn_body(uuid, "", ""))).await
+}
+
+// Prepend a custom metadata header to the content of a note
+pub fn with_metadata(metadata: serde_json::Value, text: &str) -> String {
+    format!("```json\n{}\n```\n\n{}", metadata, text)
+}
+
+pub async fn posts_list() -> Vec<String> {
+    let list: serde_json::Value = store::get_obj("posts_list").await
+        .unwrap_or(serde_json::Value::Null);
+    serde_json::from_value(list["posts"].clone()).unwrap_or(vec![])
+}
@@ -0,0 +1,97 @@
+// Previous / next post pointers
+use super::*;
+use crate::blog::PostNeighbours;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+async fn neighbours(uuid: &str) -> (Option<String>, Option<String>) {
+    let n = PostNeighbours::load(uuid).await.ok().unwrap();
+    (n.prev.map(|l| l.uuid), n.next.map(|l| l.uuid))
+}
+
+fn some(uuid: &str) -> Option<String> {
+    Some(uuid.to_owned())
+}
+
+#[wasm_bindgen_test]
+async fn neighbours_follow_the_list() {
+    setup();
+
+    for (uuid, title) in &[("0001-aaaa", "First"), ("0002-bbbb", "Second"), ("0003-cccc", "Third")] {
+        let text = with_metadata(json!({ "url": title.to_lowercase() }), "Content");
+        assert_eq!(publish(uuid, title, &text).await.status(), 200);
+    }
+    assert_eq!(neighbours("0002-bbbb").await, (some("0001-aaaa"), some("0003-cccc")));
+    assert_eq!(neighbours("0003-cccc").await, (some("0002-bbbb"), None));
+
+    let html = response_text(&get("/second/").await).await;
+    assert!(html.contains("class=\"prev-post\" href=\"/first/\""));
+    assert!(html.contains("class=\"next-post\" href=\"/third/\""));
+
+    // Taking a post off the list relinks the ones around it
+    assert_eq!(delete("0002-bbbb").await.status(), 200);
+    assert_eq!(neighbours("0001-aaaa").await, (None, some("0003-cccc")));
+    assert_eq!(neighbours("0003-cccc").await, (some("0001-aaaa"), None));
+
+    let text = with_metadata(json!({ "url": "first", "unlist": true }), "Content");
+    assert_eq!(publish("0001-aaaa", "First", &text).await.status(), 200);
+    assert_eq!(neighbours("0001-aaaa").await, (None, None));
+    assert_eq!(neighbours("0003-cccc").await, (None, None));
+    assert!(!response_text(&get("/first/").await).await.contains("class=\"post-nav\""));
+}
+
+#[wasm_bindgen_test]
+async fn pointers_follow_renames() {
+    setup();
+
+    assert_eq!(publish("0001-aaaa", "First", "Content").await.status(), 200);
+    assert_eq!(publish("0002-bbbb", "Second", "Content").await.status(), 200);
+    let text = with_metadata(json!({ "url": "renamed" }), "Content");
+    assert_eq!(publish("0001-aaaa", "Renamed", &text).await.status(), 200);
+
+    let n = PostNeighbours::load("0002-bbbb").await.ok().unwrap();
+    let prev = n.prev.unwrap();
+    assert_eq!((prev.title.as_str(), prev.url.as_str()), ("Renamed", "renamed"));
+    let html = response_text(&get("/0002/second/").await).await;
+    assert!(html.contains("class=\"prev-post\" href=\"/renamed/\""));
+}
+
+#[wasm_bindgen_test]
+async fn posts_off_the_list_have_empty_pointers() {
+    let storage = setup();
+
+    assert_eq!(publish("0001-aaaa", "First", "Content").await.status(), 200);
+    let text = with_metadata(json!({ "unlist": true }), "Content");
+    assert_eq!(publish("0002-bbbb", "Unlisted", &text).await.status(), 200);
+    let text = with_metadata(json!({ "kind": "page" }), "Content");
+    assert_eq!(publish("0003-cccc", "Page", &text).await.status(), 200);
+
+    for uuid in &["0002-bbbb", "0003-cccc"] {
+        let key = format!("post_neighbours_{}", uuid);
+        assert!(storage.keys().contains(&key), "{} has no pointers", uuid);
+        assert_eq!(neighbours(uuid).await, (None, None));
+    }
+}
+
+#[wasm_bindgen_test]
+async fn missing_pointers_are_computed_from_the_list() {
+    let storage = setup();
+
+    assert_eq!(publish("0001-aaaa", "First", "Content").await.status(), 200);
+    assert_eq!(publish("0002-bbbb", "Second", "Content").await.status(), 200);
+    // As if written before pointers existed, or edited by hand
+    store::delete("post_neighbours_0001-aaaa").await.ok().unwrap();
+    store::put_obj("post_neighbours_0002-bbbb", json!({ "prev": "0001-aaaa", "next": null }))
+        .await.ok().unwrap();
+    store::put_obj("posts_list", json!({ "version": 5, "posts": ["0001-aaaa", "0002-bbbb"] }))
+        .await.ok().unwrap();
+    assert_eq!(neighbours("0001-aaaa").await, (some("0002-bbbb"), None));
+    assert_eq!(neighbours("0002-bbbb").await, (None, None));
+    // Serving the post doesn't write them
+    assert!(response_text(&get("/0001/first/").await).await.contains("class=\"prev-post\""));
+    assert!(!storage.keys().contains(&"post_neighbours_0001-aaaa".to_owned()));
+
+    let resp = request("POST", &with_secret("/relink"), None).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(neighbours("0002-bbbb").await, (None, some("0001-aaaa")));
+}
@@ -0,0 +1,67 @@
+// Home page ordering, by publishing order or by timestamp
+use super::*;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+async fn publish_dated(uuid: &str, title: &str, date: &str) {
+    let text = with_metadata(json!({ "timestamp": date }), "Content");
+    assert_eq!(publish(uuid, title, &text).await.status(), 200);
+}
+
+fn uuids(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[wasm_bindgen_test]
+async fn posts_are_ordered_by_publishing() {
+    setup();
+    override_config(|config| config.sort_by_timestamp = false);
+
+    publish_dated("0001-aaaa", "A", "2020-01-01").await;
+    publish_dated("0002-bbbb", "B", "2010-01-01").await;
+    publish_dated("0003-cccc", "C", "2015-01-01").await;
+    publish_dated("0004-dddd", "D", "2015-01-01").await;
+    assert_eq!(posts_list().await, uuids(&["0004-dddd", "0003-cccc", "0002-bbbb", "0001-aaaa"]));
+
+    // Changing the timestamp doesn't move the post
+    publish_dated("0002-bbbb", "B", "2030-01-01").await;
+    assert_eq!(posts_list().await, uuids(&["0004-dddd", "0003-cccc", "0002-bbbb", "0001-aaaa"]));
+}
+
+#[wasm_bindgen_test]
+async fn posts_are_ordered_by_timestamp() {
+    setup();
+    override_config(|config| config.sort_by_timestamp = true);
+
+    publish_dated("0001-aaaa", "A", "2020-01-01").await;
+    publish_dated("0002-bbbb", "B", "2010-01-01").await;
+    publish_dated("0003-cccc", "C", "2015-01-01").await;
+    publish_dated("0004-dddd", "D", "2015-01-01").await;
+    // D and C have the same timestamp; the one added later goes first
+    assert_eq!(posts_list().await, uuids(&["0001-aaaa", "0004-dddd", "0003-cccc", "0002-bbbb"]));
+
+    // Changing the timestamp moves the post, both up and down
+    publish_dated("0002-bbbb", "B", "2030-01-01").await;
+    assert_eq!(posts_list().await, uuids(&["0002-bbbb", "0001-aaaa", "0004-dddd", "0003-cccc"]));
+    publish_dated("0001-aaaa", "A", "2000-01-01").await;
+    assert_eq!(posts_list().await, uuids(&["0002-bbbb", "0004-dddd", "0003-cccc", "0001-aaaa"]));
+}
+
+#[wasm_bindgen_test]
+async fn resort_sorts_existing_list() {
+    setup();
+
+    publish_dated("0001-aaaa", "A", "2020-01-01").await;
+    publish_dated("0002-bbbb", "B", "2015-01-01").await;
+    publish_dated("0003-cccc", "C", "2015-01-01").await;
+    // As if the list had been edited by hand
+    let list = json!({ "version": 100, "posts": ["0002-bbbb", "0003-cccc", "0001-aaaa"] });
+    assert!(store::put_str("posts_list", &list.to_string()).await.is_ok());
+
+    assert_eq!(request("POST", "/resort", None).await.status(), 400);
+    let resp = request("POST", &with_secret("/resort"), None).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(response_text(&resp).await, r#"{"posts":3}"#);
+    // Ties keep their order
+    assert_eq!(posts_list().await, uuids(&["0001-aaaa", "0002-bbbb", "0003-cccc"]));
+}
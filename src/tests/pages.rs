@@ -0,0 +1,106 @@
+// Standalone pages
+use super::*;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+async fn pages_list() -> Vec<String> {
+    crate::blog::PagesList::load().await.ok().unwrap()
+        .pages.into_iter().map(|p| p.uuid).collect()
+}
+
+#[wasm_bindgen_test]
+async fn pages_stay_out_of_the_post_list() {
+    setup();
+
+    assert_eq!(publish("0001-aaaa", "A post", "Content").await.status(), 200);
+    let text = with_metadata(json!({ "kind": "page", "tags": ["t"], "pinned": true }), "About me");
+    assert_eq!(publish("0002-bbbb", "About", &text).await.status(), 200);
+    let text = with_metadata(json!({ "kind": "page", "url": "stuff" }), "Things I made");
+    assert_eq!(publish("0003-cccc", "Projects", &text).await.status(), 200);
+
+    assert_eq!(posts_list().await, vec!["0001-aaaa".to_owned()]);
+    assert_eq!(pages_list().await, vec!["0002-bbbb".to_owned(), "0003-cccc".to_owned()]);
+    assert_eq!(get("/tag/t/").await.status(), 404);
+    assert!(!response_text(&get("/feed.xml").await).await.contains("About me"));
+
+    // Pages live at their slug, and every template can link to them
+    let html = response_text(&get("/about/").await).await;
+    assert!(html.contains("About me"));
+    assert!(!html.contains("class=\"post-nav\""));
+    assert!(html.contains("href=\"/stuff/\""));
+    let html = response_text(&get("/").await).await;
+    assert!(html.contains("href=\"/about/\""));
+    assert!(!html.contains("About me"));
+    assert!(!response_text(&get("/0001/a-post/").await).await.contains("class=\"post-nav\""));
+}
+
+#[wasm_bindgen_test]
+async fn pages_can_be_unlisted_or_turned_into_posts() {
+    setup();
+
+    let text = with_metadata(json!({ "kind": "page", "unlist": true }), "Content");
+    assert_eq!(publish("0001-aaaa", "Hidden", &text).await.status(), 200);
+    assert!(pages_list().await.is_empty());
+    assert_eq!(get("/hidden/").await.status(), 200);
+
+    let text = with_metadata(json!({ "kind": "page" }), "Content");
+    assert_eq!(publish("0001-aaaa", "Hidden", &text).await.status(), 200);
+    assert_eq!(pages_list().await, vec!["0001-aaaa".to_owned()]);
+
+    // The URL stays, as for any other update
+    assert_eq!(publish("0001-aaaa", "Hidden", "Content").await.status(), 200);
+    assert!(pages_list().await.is_empty());
+    assert_eq!(posts_list().await, vec!["0001-aaaa".to_owned()]);
+
+    let text = with_metadata(json!({ "kind": "page" }), "Content");
+    assert_eq!(publish("0001-aaaa", "Hidden", &text).await.status(), 200);
+    assert_eq!(delete("0001-aaaa").await.status(), 200);
+    assert!(pages_list().await.is_empty());
+
+    let text = with_metadata(json!({ "kind": "something" }), "Content");
+    assert_ne!(publish("0002-bbbb", "Bad", &text).await.status(), 200);
+}
+
+#[wasm_bindgen_test]
+async fn page_urls_must_be_usable_and_free() {
+    let storage = setup();
+
+    // Nothing to make a slug from
+    let text = with_metadata(json!({ "kind": "page" }), "Content");
+    assert_eq!(publish("0001-aaaa", "关于", &text).await.status(), 400);
+    assert!(storage.keys().is_empty());
+    let text = with_metadata(json!({ "kind": "page", "url": "about-zh" }), "Content");
+    assert_eq!(publish("0001-aaaa", "关于", &text).await.status(), 200);
+
+    // Shadowed by other routes
+    let text = with_metadata(json!({ "kind": "page" }), "Content");
+    assert_eq!(publish("0006-ffff", "Static", &text).await.status(), 400);
+    let text = with_metadata(json!({ "url": "tag/rust" }), "Content");
+    assert_eq!(publish("0006-ffff", "Post", &text).await.status(), 400);
+    let text = with_metadata(json!({ "url": "/feed.xml" }), "Content");
+    assert_eq!(publish("0006-ffff", "Post", &text).await.status(), 400);
+    assert!(!storage.keys().iter().any(|k| k.ends_with("0006-ffff")));
+    let text = with_metadata(json!({ "url": "tags" }), "Content");
+    assert_eq!(publish("0006-ffff", "Post", &text).await.status(), 200);
+    assert_eq!(get("/tags/").await.status(), 200);
+
+    // Taken by another page, or by the custom URL of a post
+    let text = with_metadata(json!({ "kind": "page" }), "First about");
+    assert_eq!(publish("0002-bbbb", "About", &text).await.status(), 200);
+    let text = with_metadata(json!({ "kind": "page" }), "Second about");
+    assert_eq!(publish("0003-cccc", "About", &text).await.status(), 409);
+    let text = with_metadata(json!({ "url": "projects" }), "A post");
+    assert_eq!(publish("0004-dddd", "Post", &text).await.status(), 200);
+    let text = with_metadata(json!({ "kind": "page" }), "A page");
+    assert_eq!(publish("0005-eeee", "Projects", &text).await.status(), 409);
+
+    assert!(response_text(&get("/about/").await).await.contains("First about"));
+    assert!(response_text(&get("/projects/").await).await.contains("A post"));
+    assert!(!storage.keys().iter().any(|k| k.ends_with("0003-cccc") || k.ends_with("0005-eeee")));
+    assert_eq!(pages_list().await, vec!["0001-aaaa".to_owned(), "0002-bbbb".to_owned()]);
+
+    // A page can still be updated at its own URL
+    let text = with_metadata(json!({ "kind": "page" }), "Updated about");
+    assert_eq!(publish("0002-bbbb", "About", &text).await.status(), 200);
+    assert!(response_text(&get("/about/").await).await.contains("Updated about"));
+}
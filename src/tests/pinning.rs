@@ -0,0 +1,47 @@
+// Pinned posts on the home page
+use super::*;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test]
+async fn pinned_posts_stay_on_top_once() {
+    setup();
+
+    let text = with_metadata(json!({ "pinned": true }), "Content");
+    assert_eq!(publish("0001-aaaa", "Pinned post", &text).await.status(), 200);
+    assert_eq!(publish("0002-bbbb", "Newer post", "Content").await.status(), 200);
+    assert_eq!(posts_list().await, vec!["0002-bbbb".to_owned(), "0001-aaaa".to_owned()]);
+
+    let html = response_text(&get("/").await).await;
+    let pinned = html.find("Pinned post").unwrap();
+    assert!(pinned < html.find("Newer post").unwrap());
+    assert_eq!(html.matches("Pinned post").count(), 1);
+    assert!(html.contains("class=\"pinned\""));
+
+    // The feed keeps the usual order
+    let rss = response_text(&get("/feed.xml").await).await;
+    assert!(rss.find("Newer post").unwrap() < rss.find("Pinned post").unwrap());
+    assert_eq!(rss.matches("Pinned post").count(), 1);
+
+    // Unpinned by publishing without the flag
+    assert_eq!(publish("0001-aaaa", "Pinned post", "Content").await.status(), 200);
+    let html = response_text(&get("/").await).await;
+    assert!(html.find("Newer post").unwrap() < html.find("Pinned post").unwrap());
+    assert!(!html.contains("class=\"pinned\""));
+}
+
+#[wasm_bindgen_test]
+async fn unlisted_posts_are_not_pinned() {
+    setup();
+
+    let text = with_metadata(json!({ "pinned": true, "unlist": true }), "Content");
+    assert_eq!(publish("0001-aaaa", "Hidden post", &text).await.status(), 200);
+    assert_eq!(publish("0002-bbbb", "Visible post", "Content").await.status(), 200);
+    assert!(!response_text(&get("/").await).await.contains("Hidden post"));
+
+    let text = with_metadata(json!({ "pinned": true }), "Content");
+    assert_eq!(publish("0001-aaaa", "Hidden post", &text).await.status(), 200);
+    assert_eq!(delete("0001-aaaa").await.status(), 200);
+    let pinned: serde_json::Value = store::get_obj("pinned_posts").await.ok().unwrap();
+    assert_eq!(pinned["posts"], json!([]));
+}
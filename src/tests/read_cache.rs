@@ -0,0 +1,79 @@
+// Request-scoped read cache in front of the storage backend
+use super::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use wasm_bindgen_test::*;
+
+// Counts how many times each key is read from the backend
+struct Counting {
+    reads: RefCell<HashMap<String, usize>>
+}
+
+impl Counting {
+    fn reads(&self, key: &str) -> usize {
+        self.reads.borrow().get(key).cloned().unwrap_or(0)
+    }
+}
+
+impl StorageHooks for Counting {
+    fn get_str<'a>(&'a self, inner: &'a MemoryStorage, key: &'a str) -> StoreFuture<'a, Option<String>> {
+        *self.reads.borrow_mut().entry(key.to_owned()).or_insert(0) += 1;
+        inner.get_str(key)
+    }
+}
+
+fn setup_counting() -> Rc<WrappedStorage<Counting>> {
+    setup_wrapped(Counting { reads: RefCell::new(HashMap::new()) })
+}
+
+#[wasm_bindgen_test]
+async fn pages_read_each_key_once() {
+    let storage = setup_counting();
+    assert_eq!(publish("0001-aaaa", "First", "Content").await.status(), 200);
+    assert_eq!(publish("0002-bbbb", "Second", "Content").await.status(), 200);
+
+    for path in &["/", "/0001/first/"] {
+        storage.hooks.reads.borrow_mut().clear();
+        assert_eq!(get(path).await.status(), 200);
+        assert!(storage.hooks.reads(&"post_by_uuid_0001-aaaa") >= 1);
+        for (key, count) in storage.hooks.reads.borrow().iter() {
+            assert_eq!(*count, 1, "{} read {} times for {}", key, count, path);
+        }
+    }
+}
+
+#[wasm_bindgen_test]
+async fn writes_invalidate_cached_reads() {
+    let storage = setup_counting();
+
+    store::with_read_cache(async {
+        assert_eq!(store::get_str("key").await.ok().unwrap(), "");
+        assert_eq!(store::get_str("key").await.ok().unwrap(), "");
+        assert_eq!(storage.hooks.reads("key"), 1);
+
+        assert!(store::put_str("key", "value").await.is_ok());
+        assert_eq!(store::get_str("key").await.ok().unwrap(), "value");
+        assert_eq!(store::get_str("key").await.ok().unwrap(), "value");
+        assert_eq!(storage.hooks.reads("key"), 2);
+
+        assert!(store::delete("key").await.is_ok());
+        assert_eq!(store::try_get_str("key").await.ok().unwrap(), None);
+        assert_eq!(storage.hooks.reads("key"), 3);
+    }).await;
+
+    // Every request starts with an empty cache
+    store::with_read_cache(async {
+        assert_eq!(store::try_get_str("key").await.ok().unwrap(), None);
+    }).await;
+    assert_eq!(storage.hooks.reads("key"), 4);
+}
+
+#[wasm_bindgen_test]
+async fn nothing_is_cached_outside_requests() {
+    let storage = setup_counting();
+
+    assert!(store::put_str("key", "value").await.is_ok());
+    assert_eq!(store::get_str("key").await.ok().unwrap(), "value");
+    assert_eq!(store::get_str("key").await.ok().unwrap(), "value");
+    assert_eq!(storage.hooks.reads("key"), 2);
+}
@@ -0,0 +1,139 @@
+// Markdown rendering and what's stored in the content cache
+use super::*;
+use wasm_bindgen_test::*;
+
+async fn content_cache(uuid: &str) -> serde_json::Value {
+    store::get_obj(&format!("content_cache_{}", uuid)).await.ok().unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn word_count_skips_code_and_counts_cjk_characters() {
+    setup();
+
+    let text = "Don't count -- twice, `inline` counts.\n\n\
+        ```rust\nfn main() { println!(\"not counted\"); }\n```\n\n\
+        中文字，日本語です";
+    assert_eq!(publish("0001-aaaa", "Words", text).await.status(), 200);
+    let cache = content_cache("0001-aaaa").await;
+    // 5 words + 3 Chinese characters + 5 Japanese characters
+    assert_eq!(cache["word_count"], 13);
+    assert_eq!(cache["reading_time"], 1);
+
+    let long = "word ".repeat(1000) + &"字".repeat(400);
+    assert_eq!(publish("0002-bbbb", "Long", &long).await.status(), 200);
+    assert_eq!(content_cache("0002-bbbb").await["reading_time"], 6);
+    assert!(response_text(&get("/0002/long/").await).await.contains("6 min read"));
+}
+
+#[wasm_bindgen_test]
+async fn headings_are_collected_into_a_nested_toc() {
+    setup();
+
+    let text = "### Intro\n\n# Part one\n\n## Details\n\n### More `details`\n\n## Other\n\n# Part two";
+    assert_eq!(publish("0001-aaaa", "Contents", text).await.status(), 200);
+    let toc = &content_cache("0001-aaaa").await["toc"];
+    let titles = |entries: &serde_json::Value| -> Vec<String> {
+        entries.as_array().unwrap().iter()
+            .map(|e| e["title"].as_str().unwrap().to_owned())
+            .collect()
+    };
+    assert_eq!(titles(toc), vec!["Intro", "Part one", "Part two"]);
+    assert_eq!(titles(&toc[1]["children"]), vec!["Details", "Other"]);
+    assert_eq!(titles(&toc[1]["children"][0]["children"]), vec!["More details"]);
+    assert_eq!(toc[1]["anchor"], "part-one");
+    assert_eq!(toc[1]["children"][0]["level"], 2);
+
+    let html = response_text(&get("/0001/contents/").await).await;
+    assert!(html.contains("<a href=\"#part-one\">Part one</a>"));
+    assert!(html.contains("<h1 id=\"part-one\">"));
+
+    assert_eq!(publish("0002-bbbb", "No headings", "Content").await.status(), 200);
+    assert!(!response_text(&get("/0002/no-headings/").await).await.contains("class=\"toc\""));
+}
+
+#[wasm_bindgen_test]
+async fn heading_anchors_are_unique_and_customizable() {
+    setup();
+
+    let text = "# Using `cargo` *well*\n\n## Notes\n\n## Notes\n\n## Notes-1\n\n\
+        ## 你好，世界\n\n## Setup {#custom_setup-id}\n\n## 🎉";
+    assert_eq!(publish("0001-aaaa", "Anchors", text).await.status(), 200);
+    let html = response_text(&get("/0001/anchors/").await).await;
+    assert!(html.contains("<h1 id=\"using-cargo-well\">Using <code>cargo</code> <em>well</em></h1>"));
+    assert!(html.contains("<h2 id=\"notes\">Notes</h2>"));
+    assert!(html.contains("<h2 id=\"notes-1\">Notes</h2>"));
+    assert!(html.contains("<h2 id=\"notes-1-1\">Notes-1</h2>"));
+    assert!(html.contains("<h2 id=\"你好世界\">"));
+    assert!(html.contains("<h2 id=\"custom_setup-id\">Setup</h2>"));
+    assert!(html.contains("<h2 id=\"section\">"));
+
+    let toc = &content_cache("0001-aaaa").await["toc"];
+    assert_eq!(toc[0]["title"], "Using cargo well");
+    let anchors: Vec<&str> = toc[0]["children"].as_array().unwrap().iter()
+        .map(|e| e["anchor"].as_str().unwrap())
+        .collect();
+    assert_eq!(anchors, vec!["notes", "notes-1", "notes-1-1", "你好世界", "custom_setup-id", "section"]);
+}
+
+#[wasm_bindgen_test]
+async fn footnotes_are_collected_at_the_end() {
+    setup();
+
+    let text = "First[^b] and second[^A], first again[^b].\n\n\
+        [^a]: The second note.\n\n<!-- More -->\n\nRest of the post.\n\n\
+        [^B]: The first note.\n\n[^unused]: Never referenced.";
+    assert_eq!(publish("0001-aaaa", "Footnotes", text).await.status(), 200);
+    let html = response_text(&get("/0001/footnotes/").await).await;
+    assert!(html.contains("First<sup class=\"footnote-reference\" id=\"fnref-b\"><a href=\"#fn-b\">1</a></sup>"));
+    assert!(html.contains("second<sup class=\"footnote-reference\" id=\"fnref-a\"><a href=\"#fn-a\">2</a></sup>"));
+    assert!(html.contains("<sup class=\"footnote-reference\" id=\"fnref-b-2\"><a href=\"#fn-b\">1</a></sup>"));
+    // Definitions come after the rest of the post, in the order they are referenced
+    let rest = html.find("Rest of the post.").unwrap();
+    let first = html.find("<li id=\"fn-b\">").unwrap();
+    let second = html.find("<li id=\"fn-a\">").unwrap();
+    assert!(rest < first && first < second);
+    assert!(html.contains("The first note. <a href=\"#fnref-b\" class=\"footnote-backref\">"));
+    assert!(html.contains("<a href=\"#fnref-b-2\" class=\"footnote-backref\">"));
+    assert!(!html.contains("Never referenced"));
+
+    let cache = content_cache("0001-aaaa").await;
+    let summary = cache["summary"].as_str().unwrap();
+    assert!(summary.contains("First and second, first again."));
+    assert!(!summary.contains("footnote"));
+
+    // Without a summary divider, the summary is the whole post minus the footnotes
+    assert_eq!(publish("0002-bbbb", "Short", "Text[^1].\n\n[^1]: Note.").await.status(), 200);
+    let cache = content_cache("0002-bbbb").await;
+    assert_eq!(cache["summary"], "<p>Text.</p>\n");
+    assert!(cache["content"].as_str().unwrap().contains("<li id=\"fn-1\">"));
+}
+
+#[wasm_bindgen_test]
+async fn math_is_typeset_if_katex_is_built_in() {
+    setup();
+
+    let text = "Inline $a \\{ b \\}$, but $5 and $10 are money.\n\n$$\n\\frac{1}{2}\n$$\n\n`$code$`";
+    assert_eq!(publish("0001-aaaa", "Math", text).await.status(), 200);
+    let cache = content_cache("0001-aaaa").await;
+    let content = cache["content"].as_str().unwrap();
+    assert!(content.contains("$5 and $10 are money."));
+    assert!(content.contains("<code>$code$</code>"));
+    let html = response_text(&get("/0001/math/").await).await;
+    if cfg!(katex) {
+        // The default theme doesn't ship the stylesheet
+        assert!(!html.contains("katex.min.css"));
+        override_config(|config| config.katex_cdn = true);
+        let html = response_text(&get("/0001/math/").await).await;
+        assert!(html.contains("https://cdn.jsdelivr.net/npm/katex@"));
+        assert!(content.contains("class=\"katex\""));
+        assert!(content.contains("class=\"katex-display\""));
+        // Markdown escapes are left for TeX inside math
+        assert!(content.contains("<annotation encoding=\"application/x-tex\">a \\{ b \\}</annotation>"));
+    } else {
+        assert!(content.contains("Inline $a { b }$"));
+        assert!(!content.contains("katex"));
+        assert!(!html.contains("katex.min.css"));
+        override_config(|config| config.katex_cdn = true);
+        assert!(!response_text(&get("/0001/math/").await).await.contains("katex.min.css"));
+    }
+}
@@ -0,0 +1,109 @@
+// Post revisions and rollback
+use super::*;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+async fn revisions(uuid: &str) -> serde_json::Value {
+    let resp = get(&with_secret(&format!("/revisions?uuid={}", uuid))).await;
+    assert_eq!(resp.status(), 200);
+    serde_json::from_str(&response_text(&resp).await).unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn rollback_restores_content_and_url() {
+    setup();
+
+    let text = with_metadata(json!({ "url": "first-url" }), "First version");
+    assert_eq!(publish("0001-aaaa", "Hello", &text).await.status(), 200);
+    let text = with_metadata(json!({ "url": "second-url" }), "Second version");
+    assert_eq!(publish("0001-aaaa", "Hello", &text).await.status(), 200);
+
+    let list = revisions("0001-aaaa").await;
+    assert_eq!(list["version"], 2);
+    assert_eq!(list["revisions"].as_array().unwrap().len(), 1);
+    assert_eq!(list["revisions"][0]["revision"], 1);
+    assert_eq!(list["revisions"][0]["url"], "first-url");
+
+    let resp = request("POST", &with_secret("/rollback?uuid=0001-aaaa&revision=1"), None).await;
+    assert_eq!(resp.status(), 200);
+    let result: serde_json::Value = serde_json::from_str(&response_text(&resp).await).unwrap();
+    assert_eq!(result["version"], 3);
+
+    let resp = get("/first-url/").await;
+    assert_eq!(resp.status(), 200);
+    assert!(response_text(&resp).await.contains("First version"));
+    let resp = get("/second-url/").await;
+    assert_eq!(resp.status(), 301);
+    assert_eq!(location(&resp), format!("{}/first-url/", BASE_URL));
+
+    // The rollback itself can be undone
+    let list = revisions("0001-aaaa").await;
+    assert_eq!(list["revisions"][0]["revision"], 2);
+    assert_eq!(list["revisions"][1]["revision"], 1);
+}
+
+#[wasm_bindgen_test]
+async fn rollback_to_a_taken_url_is_refused() {
+    setup();
+
+    let text = with_metadata(json!({ "url": "first-url" }), "First version");
+    assert_eq!(publish("0001-aaaa", "Hello", &text).await.status(), 200);
+    let text = with_metadata(json!({ "url": "second-url" }), "Second version");
+    assert_eq!(publish("0001-aaaa", "Hello", &text).await.status(), 200);
+    // Another post takes the old URL
+    let text = with_metadata(json!({ "url": "first-url" }), "Other post");
+    assert_eq!(publish("0002-bbbb", "Other", &text).await.status(), 200);
+
+    let resp = request("POST", &with_secret("/rollback?uuid=0001-aaaa&revision=1"), None).await;
+    assert_eq!(resp.status(), 409);
+    assert!(response_text(&resp).await.contains("first-url"));
+    assert!(response_text(&get("/first-url/").await).await.contains("Other post"));
+    assert!(response_text(&get("/second-url/").await).await.contains("Second version"));
+}
+
+#[wasm_bindgen_test]
+async fn rollback_action_undoes_last_update() {
+    setup();
+
+    assert_eq!(publish("0001-aaaa", "Hello", "Good").await.status(), 200);
+    assert_eq!(publish("0001-aaaa", "Hello", "Bad").await.status(), 200);
+
+    let body = sn_body("0001-aaaa", "Hello", "Bad");
+    assert_eq!(request("POST", "/rollback?secret=wrong", Some(&body)).await.status(), 401);
+    assert_eq!(request("POST", &with_secret("/rollback"), Some(&body)).await.status(), 200);
+    assert!(response_text(&get("/0001/hello/").await).await.contains("Good"));
+
+    let resp = request("POST", &with_secret("/rollback?uuid=0002-bbbb"), None).await;
+    assert_eq!(resp.status(), 404);
+    let resp = request("POST", &with_secret("/rollback?uuid=0001-aaaa&revision=42"), None).await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[wasm_bindgen_test]
+async fn old_revisions_are_dropped() {
+    let storage = setup();
+    override_config(|config| config.revisions_to_keep = 3);
+
+    for i in 0..6 {
+        assert_eq!(publish("0001-aaaa", "Hello", &format!("Version {}", i)).await.status(), 200);
+    }
+    let list = revisions("0001-aaaa").await;
+    let list: Vec<&serde_json::Value> = list["revisions"].as_array().unwrap().iter()
+        .map(|r| &r["revision"])
+        .collect();
+    assert_eq!(list, vec![5, 4, 3]);
+
+    assert_eq!(delete("0001-aaaa").await.status(), 200);
+    assert!(!storage.keys().iter().any(|k| k.starts_with("post_revision_")));
+}
+
+#[wasm_bindgen_test]
+async fn revisions_can_be_disabled() {
+    let storage = setup();
+    override_config(|config| config.revisions_to_keep = 0);
+
+    assert_eq!(publish("0001-aaaa", "Hello", "First").await.status(), 200);
+    assert_eq!(publish("0001-aaaa", "Hello", "Second").await.status(), 200);
+    assert!(revisions("0001-aaaa").await["revisions"].as_array().unwrap().is_empty());
+    assert!(!storage.keys().iter().any(|k| k.starts_with("post_revision_")));
+}
@@ -0,0 +1,77 @@
+// Scheduled publishing through the Cron Trigger entry point
+use super::*;
+use js_sys::Date;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+// Run the scheduler as if it were `date` now
+async fn run_scheduler(date: &str) -> u32 {
+    crate::handle_scheduled_rs(fake_event(), Date::parse(date)).await.ok().unwrap()
+}
+
+fn scheduled(date: &str) -> String {
+    with_metadata(json!({ "publish_at": date }), "Content")
+}
+
+#[wasm_bindgen_test]
+async fn posts_go_live_when_due() {
+    let storage = setup();
+
+    assert_eq!(publish("0001-aaaa", "Later", &scheduled("2100-01-01T00:00:00Z")).await.status(), 200);
+    assert!(posts_list().await.is_empty());
+    assert_eq!(get("/0001/later/").await.status(), 404);
+
+    assert_eq!(run_scheduler("2099-12-31T23:59:59Z").await, 0);
+    assert!(posts_list().await.is_empty());
+
+    assert_eq!(run_scheduler("2100-01-01T00:00:00Z").await, 1);
+    assert_eq!(posts_list().await, vec!["0001-aaaa".to_owned()]);
+    assert!(storage.keys().contains(&"content_cache_0001-aaaa".to_owned()));
+    assert_eq!(get("/0001/later/").await.status(), 200);
+    let post: serde_json::Value = store::get_obj("post_by_uuid_0001-aaaa").await.ok().unwrap();
+    assert_eq!(post["timestamp"], Date::parse("2100-01-01T00:00:00Z") as u64 / 1000);
+    assert_eq!(post["publish_at"], serde_json::Value::Null);
+
+    // Nothing left to do
+    assert_eq!(run_scheduler("2100-01-02T00:00:00Z").await, 0);
+}
+
+#[wasm_bindgen_test]
+async fn malformed_publish_at_is_rejected() {
+    let storage = setup();
+
+    assert_eq!(publish("0001-aaaa", "Later", &scheduled("next friday")).await.status(), 400);
+    assert!(storage.keys().is_empty());
+    assert_eq!(get("/0001/later/").await.status(), 404);
+}
+
+#[wasm_bindgen_test]
+async fn due_posts_are_listed_in_order() {
+    setup();
+
+    assert_eq!(publish("0001-aaaa", "Now", "Content").await.status(), 200);
+    let text = with_metadata(json!({ "publish_at": "2100-01-02", "tags": ["t"] }), "Content");
+    assert_eq!(publish("0002-bbbb", "Second", &text).await.status(), 200);
+    assert_eq!(publish("0003-cccc", "First", &scheduled("2100-01-01")).await.status(), 200);
+    assert_eq!(get("/tag/t/").await.status(), 404);
+
+    assert_eq!(run_scheduler("2100-01-03T00:00:00Z").await, 2);
+    assert_eq!(posts_list().await,
+        vec!["0002-bbbb".to_owned(), "0003-cccc".to_owned(), "0001-aaaa".to_owned()]);
+    assert_eq!(get("/tag/t/").await.status(), 200);
+}
+
+#[wasm_bindgen_test]
+async fn past_and_deleted_schedules() {
+    setup();
+
+    // Already due: published right away
+    assert_eq!(publish("0001-aaaa", "Past", &scheduled("2001-01-01")).await.status(), 200);
+    assert_eq!(posts_list().await, vec!["0001-aaaa".to_owned()]);
+    assert_eq!(get("/0001/past/").await.status(), 200);
+
+    assert_eq!(publish("0002-bbbb", "Gone", &scheduled("2100-01-01")).await.status(), 200);
+    assert_eq!(delete("0002-bbbb").await.status(), 200);
+    assert_eq!(run_scheduler("2100-01-02T00:00:00Z").await, 0);
+    assert_eq!(posts_list().await, vec!["0001-aaaa".to_owned()]);
+}
@@ -0,0 +1,60 @@
+// Post series and their index
+use super::*;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+async fn series_uuids(title: &str) -> Vec<String> {
+    crate::blog::Series::load(title).await.ok().unwrap()
+        .posts.into_iter().map(|p| p.uuid).collect()
+}
+
+#[wasm_bindgen_test]
+async fn series_are_ordered_and_linked() {
+    setup();
+
+    let text = with_metadata(json!({ "url": "part-two", "series": "Guide", "series_order": 2 }), "Two");
+    assert_eq!(publish("0001-aaaa", "Part two", &text).await.status(), 200);
+    let text = with_metadata(json!({ "url": "part-one", "series": "Guide", "series_order": 1 }), "One");
+    assert_eq!(publish("0002-bbbb", "Part one", &text).await.status(), 200);
+    // No explicit order: comes last
+    let text = with_metadata(json!({ "url": "extra", "series": "Guide" }), "Extra");
+    assert_eq!(publish("0003-cccc", "Extra part", &text).await.status(), 200);
+    assert_eq!(series_uuids("Guide").await,
+        vec!["0002-bbbb".to_owned(), "0001-aaaa".to_owned(), "0003-cccc".to_owned()]);
+
+    let html = response_text(&get("/part-two/").await).await;
+    assert!(html.contains("Part 2 of 3"));
+    assert!(html.contains("href=\"/part-one/\""));
+    assert!(html.contains("href=\"/extra/\""));
+    assert!(!html.contains("href=\"/part-two/\""));
+
+    // Renaming a post is reflected in the index
+    let text = with_metadata(json!({ "url": "part-one", "series": "Guide", "series_order": 1 }), "One");
+    assert_eq!(publish("0002-bbbb", "The first part", &text).await.status(), 200);
+    assert!(response_text(&get("/part-two/").await).await.contains("The first part"));
+}
+
+#[wasm_bindgen_test]
+async fn posts_leave_their_series() {
+    setup();
+
+    let text = with_metadata(json!({ "series": "Guide" }), "One");
+    assert_eq!(publish("0001-aaaa", "Part one", &text).await.status(), 200);
+    let text = with_metadata(json!({ "series": "Guide" }), "Two");
+    assert_eq!(publish("0002-bbbb", "Part two", &text).await.status(), 200);
+
+    // Moved to another series
+    let text = with_metadata(json!({ "series": "Other" }), "One");
+    assert_eq!(publish("0001-aaaa", "Part one", &text).await.status(), 200);
+    assert_eq!(series_uuids("Guide").await, vec!["0002-bbbb".to_owned()]);
+    assert_eq!(series_uuids("Other").await, vec!["0001-aaaa".to_owned()]);
+
+    // Unlisted
+    let text = with_metadata(json!({ "series": "Guide", "unlist": true }), "Two");
+    assert_eq!(publish("0002-bbbb", "Part two", &text).await.status(), 200);
+    assert!(series_uuids("Guide").await.is_empty());
+
+    // Deleted
+    assert_eq!(delete("0001-aaaa").await.status(), 200);
+    assert!(series_uuids("Other").await.is_empty());
+}
@@ -0,0 +1,70 @@
+// Tags and /tag/<name>/ pages
+use super::*;
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+async fn tag_list(tag: &str) -> Vec<String> {
+    let list: serde_json::Value = store::get_obj(&format!("tag_posts_{}", tag)).await
+        .unwrap_or(serde_json::Value::Null);
+    serde_json::from_value(list["posts"].clone()).unwrap_or(vec![])
+}
+
+#[wasm_bindgen_test]
+async fn tag_pages_list_tagged_posts() {
+    setup();
+
+    let text = with_metadata(json!({ "tags": ["rust", "web dev", " rust "] }), "Content");
+    assert_eq!(publish("0001-aaaa", "First", &text).await.status(), 200);
+    let text = with_metadata(json!({ "tags": ["rust"] }), "Content");
+    assert_eq!(publish("0002-bbbb", "Second", &text).await.status(), 200);
+    assert_eq!(publish("0003-cccc", "Third", "Content").await.status(), 200);
+
+    let post: serde_json::Value = store::get_obj("post_by_uuid_0001-aaaa").await.ok().unwrap();
+    assert_eq!(post["tags"], json!(["rust", "web dev"]));
+
+    let resp = get("/tag/rust/").await;
+    assert_eq!(resp.status(), 200);
+    let html = response_text(&resp).await;
+    assert!(html.contains("First") && html.contains("Second") && !html.contains("Third"));
+    // Same order as the home page
+    assert!(html.find("Second").unwrap() < html.find("First").unwrap());
+
+    let html = response_text(&get("/tag/web%20dev/").await).await;
+    assert!(html.contains("First") && !html.contains("Second"));
+    // Posts link to their tags
+    assert!(response_text(&get("/0001/first/").await).await.contains("/tag/web%20dev/"));
+
+    let resp = get("/tag/rust").await;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(location(&resp), format!("{}/tag/rust/", BASE_URL));
+    assert_eq!(get("/tag/nothing/").await.status(), 404);
+}
+
+#[wasm_bindgen_test]
+async fn tag_lists_follow_post_changes() {
+    setup();
+
+    let text = with_metadata(json!({ "tags": ["a", "b"] }), "Content");
+    assert_eq!(publish("0001-aaaa", "First", &text).await.status(), 200);
+    assert_eq!(tag_list("a").await, vec!["0001-aaaa".to_owned()]);
+    assert_eq!(tag_list("b").await, vec!["0001-aaaa".to_owned()]);
+
+    // Tags are replaced on every update
+    let text = with_metadata(json!({ "tags": ["b", "c"] }), "Content");
+    assert_eq!(publish("0001-aaaa", "First", &text).await.status(), 200);
+    assert!(tag_list("a").await.is_empty());
+    assert_eq!(tag_list("c").await, vec!["0001-aaaa".to_owned()]);
+    assert_eq!(get("/tag/a/").await.status(), 404);
+
+    // Unlisted posts are not listed under tags
+    let text = with_metadata(json!({ "tags": ["b", "c"], "unlist": true }), "Content");
+    assert_eq!(publish("0001-aaaa", "First", &text).await.status(), 200);
+    assert!(tag_list("b").await.is_empty());
+    assert_eq!(get("/tag/b/").await.status(), 404);
+
+    let text = with_metadata(json!({ "tags": ["b"] }), "Content");
+    assert_eq!(publish("0001-aaaa", "First", &text).await.status(), 200);
+    assert_eq!(get("/tag/b/").await.status(), 200);
+    assert_eq!(delete("0001-aaaa").await.status(), 200);
+    assert!(tag_list("b").await.is_empty());
+}
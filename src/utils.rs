@@ -45,6 +45,21 @@ macro_rules! headers(
      () => { ::web_sys::Headers::new().unwrap() };
 );
 
+// Verify the `secret` parameter in the query string of `$url`
+// against the one in config, and return early on mismatch
+// The parsed query string is bound to `$params`
+macro_rules! verify_secret {
+    ($url:expr, $params:ident) => {
+        let $params = UrlSearchParams::new_with_str(&$url.search())
+            .map_err(|_| Error::BadRequest("Failed to parse query string".into()))?;
+        if !$params.has("secret") {
+            return Err(Error::BadRequest("Secret needed".into()));
+        } else if $params.get("secret").unwrap() != crate::CONFIG.secret {
+            return Err(Error::Unauthorized("Secret mismatch".into()));
+        }
+    };
+}
+
 // Remove all non-ascii characters from string
 pub fn filter_non_ascii_alphanumeric(s: &str) -> String {
     s.chars().into_iter()
@@ -57,12 +72,16 @@ pub fn filter_non_ascii_alphanumeric(s: &str) -> String {
 // articles have the same URL by having the same title when
 // all non-ASCII characters are removed
 pub fn title_to_url(uuid: &str, title: &str) -> String {
-    let title_part = filter_non_ascii_alphanumeric(title)
+    format!("{}/{}", &uuid[0..4], title_to_slug(title))
+}
+
+// Pages live at fixed URLs, so they don't get the UUID prefix
+pub fn title_to_slug(title: &str) -> String {
+    filter_non_ascii_alphanumeric(title)
         .split_whitespace()
         .collect::<Vec<&str>>()
         .join("-")
-        .to_lowercase();
-    format!("{}/{}", &uuid[0..4], title_part)
+        .to_lowercase()
 }
 
 #[wasm_bindgen]
@@ -79,6 +98,30 @@ pub async fn sha1(s: &str) -> String {
     hex::encode(digest_arr)
 }
 
+// SHA-256 digest (raw bytes) via SubtleCrypto
+pub async fn sha256(data: &[u8]) -> Vec<u8> {
+    let mut bytes = data.to_vec();
+    let promise = crypto.subtle().digest_with_str_and_u8_array("SHA-256", &mut bytes).unwrap();
+    let buffer: ArrayBuffer = JsFuture::from(promise).await.unwrap().into();
+    Uint8Array::new(&buffer).to_vec()
+}
+
+// HMAC-SHA256 (raw bytes) via SubtleCrypto
+pub async fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let algorithm = Object::new();
+    Reflect::set(&algorithm, &"name".into(), &"HMAC".into()).unwrap();
+    Reflect::set(&algorithm, &"hash".into(), &"SHA-256".into()).unwrap();
+    let usages = Array::of1(&"sign".into());
+    let promise = crypto.subtle().import_key_with_object(
+        "raw", &Uint8Array::from(key), &algorithm, false, &usages).unwrap();
+    let key: CryptoKey = JsFuture::from(promise).await.unwrap().into();
+
+    let mut bytes = data.to_vec();
+    let promise = crypto.subtle().sign_with_str_and_u8_array("HMAC", &key, &mut bytes).unwrap();
+    let buffer: ArrayBuffer = JsFuture::from(promise).await.unwrap().into();
+    Uint8Array::new(&buffer).to_vec()
+}
+
 pub trait HeadersExt {
     fn add_cors(self) -> Self;
 }
@@ -87,6 +130,8 @@ impl HeadersExt for Headers {
     fn add_cors(self) -> Self {
         self.set("Access-Control-Allow-Origin", "*").unwrap();
         self.set("Access-Control-Allow-Headers", "*").unwrap();
+        // `/media/` accepts PUT and DELETE
+        self.set("Access-Control-Allow-Methods", "GET, POST, PUT, DELETE, OPTIONS").unwrap();
         self
     }
 }
@@ -109,6 +154,13 @@ pub enum Error {
     NotFound(String),
     BadRequest(String),
     Unauthorized(String),
+    // Some record in storage exists but cannot be decoded
+    // The reason should tell the user how to recover
+    CorruptedData(String),
+    // A write lost against a concurrent one and could not be retried
+    Conflict(String),
+    // Something that used to exist has been removed for good
+    Gone(String),
     InternalError()
 }
 
@@ -118,6 +170,9 @@ impl Error {
             Error::NotFound(_) => 404,
             Error::BadRequest(_) => 400,
             Error::Unauthorized(_) => 401,
+            Error::CorruptedData(_) => 500,
+            Error::Conflict(_) => 409,
+            Error::Gone(_) => 410,
             Error::InternalError() => 500
         }
     }
@@ -135,6 +190,15 @@ impl Into<String> for Error {
             Error::Unauthorized(reason) => {
                 format!("Unauthorized, Reason: {}", reason)
             },
+            Error::CorruptedData(reason) => {
+                format!("Corrupted Data, Reason: {}", reason)
+            },
+            Error::Conflict(reason) => {
+                format!("Conflict, Reason: {}", reason)
+            },
+            Error::Gone(reason) => {
+                format!("Gone, Reason: {}", reason)
+            },
             Error::InternalError() => {
                 format!("Internal Errror")
             }
@@ -167,15 +231,65 @@ pub struct Config {
     pub extra_remote_proxy_whitelist: Option<Vec<String>>,
     // Preferred URL of the blog for "Open Post" and "Open Blog" options in SN
     // Must NOT include the trailing "/"
-    pub preferred_url: Option<String>
+    pub preferred_url: Option<String>,
+    // Where to store posts and everything else
+    // Defaults to Cloudflare Workers KV if omitted
+    pub storage: Option<StorageConfig>,
+    // How many previous versions of each post to keep for rollback
+    // 0 disables revisions
+    #[serde(default = "default_revisions_to_keep")]
+    pub revisions_to_keep: usize,
+    // Keep the home page sorted by post timestamps instead of
+    // the order in which posts were first published
+    #[serde(default)]
+    pub sort_by_timestamp: bool,
+    // Load the KaTeX stylesheet from jsDelivr if the theme doesn't ship one
+    #[serde(default)]
+    pub katex_cdn: bool,
+    // Authors that posts can be attributed to via `author` in their metadata
+    // Keys are author IDs, as used in metadata and `/author/<id>/`
+    #[serde(default)]
+    pub authors: HashMap<String, Author>
+}
+
+#[derive(Deserialize)]
+pub struct Author {
+    // Display name
+    pub name: String,
+    pub bio: Option<String>,
+    // URL of the avatar image
+    pub avatar: Option<String>,
+    // The author's own website
+    pub url: Option<String>,
+    // Only used in the RSS feed, which requires one for `<author>`
+    pub email: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    Kv,
+    S3(crate::store::S3Config)
 }
 
 fn default_maxage() -> u64 {
     60 * 60 * 24 * 7 // default to a week
 }
 
+fn default_revisions_to_keep() -> usize {
+    10
+}
+
 include!(concat!(env!("OUT_DIR"), "/build_timestamp.rs"));
 
+// Escape a string for use in a double-quoted HTML attribute
+pub fn escape_html_attr(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
 // Strip HTML tags from a string via JS binding
 pub fn strip_html_tags(s: &str) -> String {
     let js_str: JsString = s.into();
@@ -31,71 +31,31 @@ window.onload = function() {
   };
   modal.onclick = span.onclick;
   modalImg.onclick = (ev) => ev.stopPropagation();
-  // Table-of-Contents generation
-  // Don't do this on mobile; it's horrible
+  // Highlight the current section in the table of contents
+  // (rendered by the server; hidden on mobile)
+  if (document.getElementsByClassName("toc").length == 0) return;
   if (window.matchMedia("(max-width: 1000px)").matches) return;
 
-  let level = 0;
-  let maxLevel = 0;
-  let toc = "";
-  content[0].querySelectorAll("h1, h2, h3, h4, h5").forEach((elem) => {
-    let openLevel = parseInt(elem.tagName.toLowerCase().replace("h", ""));
-    if (openLevel > level) {
-      toc += (new Array(openLevel - level + 1)).join("<ul>");
-    } else if (openLevel < level) {
-      toc += (new Array(level - openLevel + 1)).join("</ul>");
-    }
-
-    level = openLevel;
-    if (level > maxLevel) maxLevel = level;
-
-    let anchor = elem.getAttribute("id");
-    let titleText = elem.innerText;
-    toc += "<li><a href=\"#" + anchor + "\">" + titleText + "</a></li>";
-  });
-
-  if (level) {
-    toc += (new Array(level + 1)).join("</ul>");
-  }
-
-  if (maxLevel > 1) {
-    document.getElementsByClassName("toc")[0].innerHTML = toc;
-    document.getElementsByClassName("toc-wrapper")[0].className = "toc-wrapper"; // remove hidden
-
-    // Get rid of ul layers that have only one ul child
-    removeTrivialUlLayer(document.getElementsByClassName("toc")[0]);
-
-    var curAnchorLink = null;
-    window.onscroll = (ev) => {
-      let anchor = findClosestAnchor(content[0].querySelectorAll("h1, h2, h3, h4, h5"));
-      let name = anchor.getAttribute("id");
-      let tocLink = document.querySelector("a[href=\"#" + name + "\"").parentElement;
-      if (tocLink != curAnchorLink) {
-        tocLink.className = "current";
-        if (curAnchorLink != null) {
-          curAnchorLink.className = "";
-        }
-        curAnchorLink = tocLink;
+  var curAnchorLink = null;
+  window.onscroll = (ev) => {
+    let anchor = findClosestAnchor(content[0].querySelectorAll("h1[id], h2[id], h3[id], h4[id], h5[id], h6[id]"));
+    if (!anchor) return;
+    let name = anchor.getAttribute("id");
+    let link = document.querySelector(".toc a[href=\"#" + name + "\"]");
+    if (!link) return;
+    let tocLink = link.parentElement;
+    if (tocLink != curAnchorLink) {
+      tocLink.className = "current";
+      if (curAnchorLink != null) {
+        curAnchorLink.className = "";
       }
-    };
+      curAnchorLink = tocLink;
+    }
+  };
 
-    window.onscroll();
-  }
+  window.onscroll();
 };
 
-function removeTrivialUlLayer(elem) {
-  let children = elem.getElementsByTagName("ul");
-  if (elem.childNodes.length == 1 && children.length == 1) {
-    // Every child is a ul
-    elem.innerHTML = children[0].innerHTML;
-    removeTrivialUlLayer(elem);
-  } else {
-    for (const child of children) {
-      removeTrivialUlLayer(child);
-    }
-  }
-}
-
 // <https://stackoverflow.com/questions/10642587/finding-closest-anchor-href-via-scrolloffset>
 // findPos : courtesy of @ppk - see http://www.quirksmode.org/js/findpos.html
 var findPos = function (obj) {
@@ -10,3 +10,16 @@ async function handleRequest(ev, request) {
   const rust = await import("./pkg/index");
   return await rust.handle_request_rs(ev, request);
 }
+
+addEventListener('scheduled', event => {
+  event.waitUntil(handleScheduled(event))
+})
+
+/**
+ * Publish scheduled posts (Cron Triggers)
+ * @param {ScheduledEvent} event
+ */
+async function handleScheduled(ev) {
+  const rust = await import("./pkg/index");
+  return await rust.handle_scheduled_rs(ev, ev.scheduledTime);
+}